@@ -1,5 +1,31 @@
-use clap::{App,Arg};
-use spritesheet_gen::{SpriteSheetGenConfig,sprite_sheet_gen};
+use clap::{App,Arg,SubCommand};
+use spritesheet_gen::{HeightRounding,SpriteGrouping,SpriteOverride,SpriteSheetGenConfig,sprite_sheet_gen};
+use spritesheet_gen::unpack::unpack_atlas;
+use spritesheet_gen::anneal::AnnealConfig;
+use spritesheet_gen::packer::{PackerKind,SortOrder};
+use spritesheet_gen::max_rect::{FreeRectChoiceHeuristic,Rect};
+use spritesheet_gen::skyline::SkylineHeuristic;
+use spritesheet_gen::shelf::ShelfHeuristic;
+use spritesheet_gen::guillotine::{RectChoiceHeuristic,SplitHeuristic};
+use spritesheet_gen::font::{FontAtlasConfig,FntFormat,font_atlas_gen};
+use spritesheet_gen::slice::{GridCells,GridSlice,IslandSlice};
+
+// "AxB[,margin[,spacing]]"
+fn parse_grid_spec(spec:&str) -> Option<(u32,u32,u32,u32)> {
+    let mut parts = spec.split(',');
+    let mut size = parts.next()?.split('x');
+    let a = size.next()?.trim().parse().ok()?;
+    let b = size.next()?.trim().parse().ok()?;
+    let margin = parts.next().map(|s| s.trim().parse().ok()).unwrap_or(Some(0))?;
+    let spacing = parts.next().map(|s| s.trim().parse().ok()).unwrap_or(Some(0))?;
+    Some((a,b,margin,spacing))
+}
+
+// "X,Y" or "X,Y,W,H"
+fn parse_numbers(spec:&str) -> Option<Vec<i32>> {
+    spec.split(',').map(|s| s.trim().parse().ok()).collect()
+}
+
 fn main() {
     let matchs = App::new("spritesheet-gen")
                     .version("0.1.0")
@@ -9,7 +35,98 @@ fn main() {
                     .arg(Arg::with_name("outfile").short("o").long("outfile").value_name("OutFile").help("output file name").required(false))
                     .arg(Arg::with_name("rotation").short("r").long("rotation").value_name("Rotation").help("is rotation").required(false))
                     .arg(Arg::with_name("padding").short("p").long("padding").value_name("Padding").help("padding size").required(false))
+                    .arg(Arg::with_name("border-padding").long("border-padding").value_name("Padding").help("space along the atlas edges, default padding").required(false))
+                    .arg(Arg::with_name("shape-padding").long("shape-padding").value_name("Padding").help("space between sprites, default twice padding").required(false))
+                    .arg(Arg::with_name("packer").long("packer").value_name("Packer").possible_values(&["maxrects","skyline","guillotine","shelf"]).help("packing algorithm").required(false))
+                    .arg(Arg::with_name("heuristic").long("heuristic").value_name("Heuristic")
+                        .possible_values(&["best-short-side","best-long-side","best-area","bottom-left","contact-point"])
+                        .help("maxrects free rect choice").required(false))
+                    .arg(Arg::with_name("skyline-heuristic").long("skyline-heuristic").value_name("Heuristic").possible_values(&["bottom-left","min-waste"]).help("skyline placement rule").required(false))
+                    .arg(Arg::with_name("shelf-heuristic").long("shelf-heuristic").value_name("Heuristic").possible_values(&["next-fit","first-fit","best-height"]).help("shelf choice rule").required(false))
+                    .arg(Arg::with_name("waste-map").long("waste-map").help("reuse the gaps left by the skyline and shelf packers"))
+                    .arg(Arg::with_name("guillotine-choice").long("guillotine-choice").value_name("Heuristic")
+                        .possible_values(&["best-area","best-short-side","best-long-side","worst-area","worst-short-side","worst-long-side"])
+                        .help("guillotine free rect choice").required(false))
+                    .arg(Arg::with_name("guillotine-split").long("guillotine-split").value_name("Split")
+                        .possible_values(&["shorter-leftover","longer-leftover","min-area","max-area","shorter-axis","longer-axis"])
+                        .help("guillotine split rule").required(false))
+                    .arg(Arg::with_name("no-guillotine-merge").long("no-guillotine-merge").help("don't merge guillotine free rects"))
+                    .arg(Arg::with_name("sort").long("sort").value_name("Order").possible_values(&["none","area","perimeter","max-side","width","height"]).help("sprite insertion order").required(false))
+                    .arg(Arg::with_name("max-pages").long("max-pages").value_name("Pages").help("maximum number of atlas pages").required(false))
+                    .arg(Arg::with_name("try-all").long("try-all").help("try every packer, heuristic and sort order and keep the densest layout"))
+                    .arg(Arg::with_name("optimize").long("optimize").value_name("Iterations").help("search insertion orders for a denser layout").required(false))
+                    .arg(Arg::with_name("optimize-seconds").long("optimize-seconds").value_name("Seconds").help("time limit of the order search").required(false))
+                    .arg(Arg::with_name("seed").long("seed").value_name("Seed").help("seed of the order search").required(false))
+                    .arg(Arg::with_name("reserve").long("reserve").value_name("X,Y,W,H").help("keep a region empty on every page").multiple(true).number_of_values(1).required(false))
+                    .arg(Arg::with_name("pin").long("pin").value_names(&["Name","X,Y"]).help("place a sprite at a fixed position on the first page").multiple(true).number_of_values(2).required(false))
+                    .arg(Arg::with_name("group-by").long("group-by").value_name("Grouping").possible_values(&["subfolder","prefix"]).help("keep each group of sprites on one page").required(false))
+                    .arg(Arg::with_name("group-separator").long("group-separator").value_name("Char").help("name separator of prefix groups, default _").required(false))
+                    .arg(Arg::with_name("group-manifest").long("group-manifest").value_name("File").help("json file listing the sprites of each group").required(false))
+                    .arg(Arg::with_name("overrides").long("overrides").value_name("File").help("json file with per-sprite rotation and padding").required(false))
+                    .arg(Arg::with_name("strip").long("strip").value_name("Rounding").help("fixed width, height grows to fit: tight, pow2 or a multiple N").required(false))
+                    .arg(Arg::with_name("page-sizes").long("page-sizes").value_name("Sizes").help("smaller sizes the last page may shrink to, e.g. 1024,512x256").required(false))
+                    .arg(Arg::with_name("align").long("align").value_name("N").help("align placements and page size to multiples of N, e.g. 4 for block compression").required(false))
+                    .arg(Arg::with_name("white-block").long("white-block").value_name("Size").help("add an opaque white block to every page").required(false))
+                    .arg(Arg::with_name("white-block-name").long("white-block-name").value_name("Name").help("sprite name of the white block").required(false))
+                    .arg(Arg::with_name("grid").long("grid").value_names(&["File","WxH[,Margin[,Spacing]]"]).help("slice a uniform grid sheet by cell size").multiple(true).number_of_values(2).required(false))
+                    .arg(Arg::with_name("grid-count").long("grid-count").value_names(&["File","ColsxRows[,Margin[,Spacing]]"]).help("slice a uniform grid sheet by cell count").multiple(true).number_of_values(2).required(false))
+                    .arg(Arg::with_name("islands").long("islands").value_name("File").help("extract connected opaque regions of an image as sprites").multiple(true).number_of_values(1).required(false))
+                    .arg(Arg::with_name("alpha-threshold").long("alpha-threshold").value_name("Alpha").help("alpha above which a pixel belongs to an island").required(false))
+                    .arg(Arg::with_name("merge-distance").long("merge-distance").value_name("Pixels").help("merge islands closer than this distance").required(false))
+                    .subcommand(SubCommand::with_name("unpack")
+                        .about("split an atlas back into individual images")
+                        .arg(Arg::with_name("atlas").short("a").long("atlas").value_name("Atlas").help("atlas image").required(true))
+                        .arg(Arg::with_name("desc").short("j").long("desc").value_name("Desc").help("atlas descriptor json").required(true))
+                        .arg(Arg::with_name("outdir").short("o").long("outdir").value_name("OutDir").help("output folder").required(false)))
+                    .subcommand(SubCommand::with_name("font")
+                        .about("generate a bitmap font atlas with BMFont descriptors")
+                        .arg(Arg::with_name("font").short("f").long("font").value_name("FontFile").help("ttf/otf font file").required(true))
+                        .arg(Arg::with_name("sizes").short("s").long("sizes").value_name("Sizes").help("comma separated pixel sizes").required(false))
+                        .arg(Arg::with_name("chars").short("c").long("chars").value_name("Chars").help("characters to include").required(false))
+                        .arg(Arg::with_name("charfile").long("charfile").value_name("CharFile").help("file with characters to include").required(false))
+                        .arg(Arg::with_name("width").short("w").long("width").value_name("Width").help("image width").required(false))
+                        .arg(Arg::with_name("height").short("h").long("height").value_name("Height").help("image height").required(false))
+                        .arg(Arg::with_name("padding").short("p").long("padding").value_name("Padding").help("padding size").required(false))
+                        .arg(Arg::with_name("format").long("format").value_name("Format").possible_values(&["text","xml","both"]).help("fnt format").required(false))
+                        .arg(Arg::with_name("outfile").short("o").long("outfile").value_name("OutFile").help("output file name").required(false)))
                     .get_matches();
+    if let Some(unpack_matchs) = matchs.subcommand_matches("unpack") {
+        let atlas = unpack_matchs.value_of("atlas").unwrap();
+        let desc = unpack_matchs.value_of("desc").unwrap();
+        let out_dir = unpack_matchs.value_of("outdir").unwrap_or("./");
+        unpack_atlas(atlas, desc, out_dir).unwrap();
+        return;
+    }
+    if let Some(font_matchs) = matchs.subcommand_matches("font") {
+        let mut font_cfg = FontAtlasConfig::default();
+        font_cfg.set_font_file(font_matchs.value_of("font").unwrap());
+        if let Some(sizes) = font_matchs.value_of("sizes") {
+            font_cfg.set_sizes(sizes.split(',').filter_map(|s| s.trim().parse().ok()).collect());
+        }
+        if let Some(chars) = font_matchs.value_of("chars") {
+            font_cfg.set_chars(chars);
+        }
+        if let Some(char_file) = font_matchs.value_of("charfile") {
+            let chars = std::fs::read_to_string(char_file).expect("can't read char file");
+            font_cfg.set_chars(&chars.replace(['\n','\r'],""));
+        }
+        let w = font_matchs.value_of("width").and_then(|w| w.parse().ok()).unwrap_or(512);
+        let h = font_matchs.value_of("height").and_then(|h| h.parse().ok()).unwrap_or(512);
+        font_cfg.set_size(w,h);
+        if let Some(p) = font_matchs.value_of("padding") {
+            font_cfg.set_padding(p.parse().unwrap_or(1));
+        }
+        match font_matchs.value_of("format") {
+            Some("xml") => font_cfg.set_format(FntFormat::Xml),
+            Some("both") => font_cfg.set_format(FntFormat::Both),
+            _ => {}
+        }
+        if let Some(out_name) = font_matchs.value_of("outfile") {
+            font_cfg.set_out_file(out_name);
+        }
+        font_atlas_gen(font_cfg).unwrap();
+        return;
+    }
     let dir = matchs.value_of("dir").unwrap_or("./");
     let mut cfg = SpriteSheetGenConfig::default();
     cfg.set_dir(dir);
@@ -28,5 +145,132 @@ fn main() {
     if let Some(r) = matchs.value_of("padding") {
         cfg.set_padding(r.parse().unwrap_or(2));   
     }
-    sprite_sheet_gen(cfg).unwrap();
+    if let Some(p) = matchs.value_of("border-padding") {
+        cfg.set_border_padding(p.parse().unwrap_or(2));
+    }
+    if let Some(p) = matchs.value_of("shape-padding") {
+        cfg.set_shape_padding(p.parse().unwrap_or(4));
+    }
+    if let Some(packer) = matchs.value_of("packer") {
+        cfg.set_packer(packer.parse::<PackerKind>().unwrap());
+    }
+    if let Some(heuristic) = matchs.value_of("heuristic") {
+        cfg.set_heuristic(heuristic.parse::<FreeRectChoiceHeuristic>().unwrap());
+    }
+    if let Some(heuristic) = matchs.value_of("skyline-heuristic") {
+        cfg.set_skyline_heuristic(heuristic.parse::<SkylineHeuristic>().unwrap());
+    }
+    if let Some(heuristic) = matchs.value_of("shelf-heuristic") {
+        cfg.set_shelf_heuristic(heuristic.parse::<ShelfHeuristic>().unwrap());
+    }
+    cfg.set_use_waste_map(matchs.is_present("waste-map"));
+    let guillotine_choice = matchs.value_of("guillotine-choice").unwrap_or("best-short-side").parse::<RectChoiceHeuristic>().unwrap();
+    let guillotine_split = matchs.value_of("guillotine-split").unwrap_or("shorter-leftover").parse::<SplitHeuristic>().unwrap();
+    cfg.set_guillotine_heuristic(guillotine_choice,guillotine_split);
+    cfg.set_guillotine_merge(!matchs.is_present("no-guillotine-merge"));
+    if let Some(order) = matchs.value_of("sort") {
+        cfg.set_sort_order(order.parse::<SortOrder>().unwrap());
+    }
+    if let Some(pages) = matchs.value_of("max-pages") {
+        cfg.set_max_pages(pages.parse().unwrap_or(1));
+    }
+    cfg.set_try_all(matchs.is_present("try-all"));
+    if matchs.is_present("optimize") || matchs.is_present("optimize-seconds") {
+        let mut optimize = AnnealConfig::default();
+        if let Some(iterations) = matchs.value_of("optimize") {
+            optimize.iterations = iterations.parse().expect("bad optimize iterations");
+        }
+        if let Some(seconds) = matchs.value_of("optimize-seconds") {
+            let seconds = seconds.parse().expect("bad optimize seconds");
+            optimize.time_limit = Some(std::time::Duration::try_from_secs_f64(seconds).expect("optimize seconds must be a non-negative number"));
+            if !matchs.is_present("optimize") {
+                optimize.iterations = usize::MAX;
+            }
+        }
+        if let Some(seed) = matchs.value_of("seed") {
+            optimize.seed = seed.parse().unwrap_or(optimize.seed);
+        }
+        cfg.set_optimize(optimize);
+    }
+    if let Some(regions) = matchs.values_of("reserve") {
+        for region in regions {
+            match parse_numbers(region).as_deref() {
+                Some(&[x,y,width,height]) => cfg.add_reserved_region(Rect {x,y,width,height}),
+                _ => panic!("bad region {}",region)
+            }
+        }
+    }
+    if let Some(file) = matchs.value_of("group-manifest") {
+        cfg.set_grouping(SpriteGrouping::load_manifest(std::path::Path::new(file)).unwrap());
+    } else if let Some(grouping) = matchs.value_of("group-by") {
+        let separator = matchs.value_of("group-separator").and_then(|s| s.chars().next()).unwrap_or('_');
+        cfg.set_grouping(if grouping == "subfolder" { SpriteGrouping::Subfolder } else { SpriteGrouping::Prefix(separator) });
+    }
+    if let Some(file) = matchs.value_of("overrides") {
+        for (name,sprite_override) in SpriteOverride::load_manifest(std::path::Path::new(file)).unwrap() {
+            cfg.set_sprite_override(&name,sprite_override);
+        }
+    }
+    if let Some(rounding) = matchs.value_of("strip") {
+        cfg.set_strip_packing(match rounding {
+            "tight" => HeightRounding::Tight,
+            "pow2" => HeightRounding::PowerOfTwo,
+            n => HeightRounding::Multiple(n.parse().expect("bad strip rounding"))
+        });
+    }
+    if let Some(sizes) = matchs.value_of("page-sizes") {
+        // "N" for a square page or "WxH"
+        let page_sizes = sizes.split(',').map(|size| {
+            let mut dims = size.trim().split('x').map(|n| n.parse::<u32>().expect("bad page size"));
+            let w = dims.next().unwrap();
+            (w,dims.next().unwrap_or(w))
+        }).collect();
+        cfg.set_page_sizes(page_sizes);
+    }
+    if let Some(alignment) = matchs.value_of("align") {
+        cfg.set_alignment(alignment.parse().unwrap_or(1));
+    }
+    if let Some(size) = matchs.value_of("white-block") {
+        cfg.set_white_block(matchs.value_of("white-block-name").unwrap_or("white"),size.parse().unwrap_or(4));
+    }
+    let pins:Vec<&str> = matchs.values_of("pin").map(|v| v.collect()).unwrap_or_default();
+    for pair in pins.chunks(2) {
+        match parse_numbers(pair[1]).as_deref() {
+            Some(&[x,y]) if x >= 0 && y >= 0 => cfg.pin_sprite(pair[0],x as u32,y as u32),
+            _ => panic!("bad pin position {}",pair[1])
+        }
+    }
+    for (arg_name,by_count) in [("grid",false),("grid-count",true)].iter() {
+        let values:Vec<&str> = matchs.values_of(arg_name).map(|v| v.collect()).unwrap_or_default();
+        for pair in values.chunks(2) {
+            let (a,b,margin,spacing) = parse_grid_spec(pair[1]).expect("bad grid spec");
+            let cells = if *by_count { GridCells::Count(a,b) } else { GridCells::Size(a,b) };
+            cfg.add_grid_sheet(pair[0],GridSlice {cells,margin,spacing});
+        }
+    }
+    if let Some(files) = matchs.values_of("islands") {
+        let mut islands = IslandSlice::default();
+        if let Some(a) = matchs.value_of("alpha-threshold") {
+            islands.alpha_threshold = a.parse().unwrap_or(0);
+        }
+        if let Some(d) = matchs.value_of("merge-distance") {
+            islands.merge_distance = d.parse().unwrap_or(0);
+        }
+        for file in files {
+            cfg.add_island_sheet(file,islands);
+        }
+    }
+    let stats = sprite_sheet_gen(cfg).unwrap();
+    if let Some((packer,sort_order)) = &stats.best_packer {
+        println!("best layout: {} sorted by {:?}",packer,sort_order);
+    }
+    if let Some((before,after)) = &stats.optimized {
+        println!("optimized layout: {} page(s), {} unplaced, {:.2}% occupancy (was {} page(s), {} unplaced, {:.2}%)",
+                 after.pages,after.unplaced,after.occupancy * 100.0,before.pages,before.unplaced,before.occupancy * 100.0);
+    }
+    println!("packed {} sprites on {} page(s), {:.2}% occupancy, {} wasted pixels",
+             stats.sprites_packed,stats.pages,stats.occupancy * 100.0,stats.wasted_pixels);
+    if stats.sprites_unplaced > 0 {
+        eprintln!("{} sprites didn't fit",stats.sprites_unplaced);
+    }
 }
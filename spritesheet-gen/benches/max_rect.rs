@@ -0,0 +1,35 @@
+use criterion::{criterion_group,criterion_main,BenchmarkId,Criterion};
+use spritesheet_gen::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack};
+
+// Deterministic sprite sizes between 4 and 35 pixels, like icons and glyphs.
+fn synthetic_sizes(count:usize) -> Vec<(i32,i32)> {
+    let mut seed:u32 = 0x2545_f491;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    (0..count).map(|_| (4 + (next() % 32) as i32,4 + (next() % 32) as i32)).collect()
+}
+
+fn pack(sizes:&[(i32,i32)],method:FreeRectChoiceHeuristic) -> usize {
+    let mut bin = MaxRectsBinPack::new(4096,4096,false);
+    sizes.iter().filter(|&&(w,h)| bin.insert(w,h,method).0.height > 0).count()
+}
+
+fn bench_insert(c:&mut Criterion) {
+    let mut group = c.benchmark_group("max_rect_insert");
+    group.sample_size(10);
+    for &count in [1000usize,5000,10000].iter() {
+        let sizes = synthetic_sizes(count);
+        for &(name,method) in [("best-short-side",FreeRectChoiceHeuristic::BestShortSideFit),("best-area",FreeRectChoiceHeuristic::BestAreaFit),
+                               ("contact-point",FreeRectChoiceHeuristic::ContactPointRule)].iter() {
+            group.bench_with_input(BenchmarkId::new(name,count),&sizes,|b,sizes| b.iter(|| pack(sizes,method)));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches,bench_insert);
+criterion_main!(benches);
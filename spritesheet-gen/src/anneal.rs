@@ -0,0 +1,120 @@
+use std::time::{Duration,Instant};
+
+/// Budget and seed of the insertion order search. The same seed and iteration count
+/// always give the same result; a time limit can stop the search earlier.
+#[derive(Debug,Clone,Copy)]
+pub struct AnnealConfig {
+    pub iterations:usize,
+    pub time_limit:Option<Duration>,
+    pub seed:u64,
+    /// Starting temperature, in units of the cost. It cools linearly to zero over the
+    /// iterations or the time limit, whichever runs out first.
+    pub temperature:f64
+}
+
+impl Default for AnnealConfig {
+    fn default() -> Self {
+        AnnealConfig {
+            iterations:1000,
+            time_limit:None,
+            seed:1,
+            temperature:0.02
+        }
+    }
+}
+
+// xorshift64*, enough for picking moves reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed:u64) -> Self {
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self,n:usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Simulated annealing over an insertion order and a flip flag per item. Each step swaps two
+/// items or flips one of the `flippable` ones, and `cost` scores the candidate (lower is better).
+/// Returns the best order and flips seen.
+pub fn anneal<F:FnMut(&[usize],&[bool]) -> f64>(order:Vec<usize>,flippable:&[bool],cfg:&AnnealConfig,mut cost:F) -> (Vec<usize>,Vec<bool>) {
+    let start = Instant::now();
+    let mut rng = Rng::new(cfg.seed);
+    let flippable_items:Vec<usize> = (0..flippable.len()).filter(|&i| flippable[i]).collect();
+    let mut current_order = order;
+    let mut current_flips = vec![false;flippable.len()];
+    let mut current_cost = cost(&current_order,&current_flips);
+    let mut best = (current_order.clone(),current_flips.clone());
+    let mut best_cost = current_cost;
+    if current_order.len() < 2 && flippable_items.is_empty() {
+        return best;
+    }
+    for iteration in 0..cfg.iterations {
+        if cfg.time_limit.map(|limit| start.elapsed() >= limit).unwrap_or(false) {
+            break;
+        }
+        let mut order = current_order.clone();
+        let mut flips = current_flips.clone();
+        if !flippable_items.is_empty() && (order.len() < 2 || rng.below(4) == 0) {
+            let item = flippable_items[rng.below(flippable_items.len())];
+            flips[item] = !flips[item];
+        } else {
+            let a = rng.below(order.len());
+            let b = rng.below(order.len());
+            order.swap(a,b);
+        }
+        let candidate_cost = cost(&order,&flips);
+        let mut progress = iteration as f64 / cfg.iterations as f64;
+        if let Some(limit) = cfg.time_limit {
+            progress = f64::max(progress, start.elapsed().as_secs_f64() / limit.as_secs_f64());
+        }
+        let temperature = cfg.temperature * (1.0 - f64::min(progress, 1.0));
+        let delta = candidate_cost - current_cost;
+        if delta <= 0.0 || (temperature > 0.0 && rng.unit() < (-delta / temperature).exp()) {
+            current_order = order;
+            current_flips = flips;
+            current_cost = candidate_cost;
+            if current_cost < best_cost {
+                best_cost = current_cost;
+                best = (current_order.clone(),current_flips.clone());
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anneal,AnnealConfig};
+
+    #[test]
+    fn test_anneal_sorts() {
+        // Cost is the number of out-of-order pairs plus the number of flipped items.
+        let cost = |order:&[usize],flips:&[bool]| {
+            let mut inversions = flips.iter().filter(|&&flip| flip).count();
+            for i in 0..order.len() {
+                inversions += order[i + 1..].iter().filter(|&&b| b < order[i]).count();
+            }
+            inversions as f64
+        };
+        let cfg = AnnealConfig {iterations:5000,temperature:1.0,..AnnealConfig::default()};
+        let flippable = [true,false,true,false,true,false];
+        let (order,flips) = anneal(vec![5,3,1,4,0,2],&flippable,&cfg,cost);
+        assert_eq!(order,vec![0,1,2,3,4,5]);
+        assert!(flips.iter().all(|&flip| !flip));
+        assert_eq!(anneal(vec![5,3,1,4,0,2],&flippable,&cfg,cost),(order,flips));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::max_rect;
+use ab_glyph::{point,Font,FontVec,PxScale,ScaleFont};
+use image::{Rgba,RgbaImage};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FntFormat {
+    Text,
+    Xml,
+    Both
+}
+
+pub struct FontAtlasConfig {
+    font_file:String,
+    sizes:Vec<u32>,
+    chars:String,
+    width:u32,
+    height:u32,
+    padding:u32,
+    format:FntFormat,
+    out_file:Option<String>,
+}
+
+impl Default for FontAtlasConfig {
+    fn default() -> Self {
+        FontAtlasConfig {
+            font_file:String::new(),
+            sizes:vec![32],
+            chars:(32u8..127).map(|c| c as char).collect(),
+            width:512,
+            height:512,
+            padding:1,
+            format:FntFormat::Text,
+            out_file:None
+        }
+    }
+}
+
+impl FontAtlasConfig {
+    pub fn set_font_file(&mut self,path:&str) {
+        self.font_file = String::from(path);
+    }
+    pub fn set_sizes(&mut self,sizes:Vec<u32>) {
+        self.sizes = sizes;
+    }
+    pub fn set_chars(&mut self,chars:&str) {
+        self.chars = String::from(chars);
+    }
+    pub fn set_size(&mut self,w:u32,h:u32) {
+        self.width = w;
+        self.height = h;
+    }
+    pub fn set_padding(&mut self,padding:u32) {
+        self.padding = padding;
+    }
+    pub fn set_format(&mut self,format:FntFormat) {
+        self.format = format;
+    }
+    pub fn set_out_file(&mut self,out_file:&str) {
+        self.out_file = Some(String::from(out_file));
+    }
+}
+
+/// One glyph entry of an AngelCode BMFont file.
+#[derive(Debug,Clone,Default)]
+pub struct FntChar {
+    pub id:u32,
+    pub rect:max_rect::Rect,
+    pub xoffset:i32,
+    pub yoffset:i32,
+    pub xadvance:i32
+}
+
+/// The contents of an AngelCode BMFont descriptor with a single page.
+#[derive(Debug,Clone,Default)]
+pub struct BmFont {
+    pub face:String,
+    pub size:u32,
+    /// Gap between glyphs in the texture.
+    pub spacing:u32,
+    pub line_height:i32,
+    pub base:i32,
+    pub scale_w:u32,
+    pub scale_h:u32,
+    pub page_file:String,
+    pub chars:Vec<FntChar>,
+    pub kernings:Vec<(u32,u32,i32)>
+}
+
+/// The text format has no escapes of its own (readers show a backslash literally), so double
+/// quotes, which would end the value, become single quotes and line breaks, which would end the
+/// record, become spaces.
+fn text_escape(s:&str) -> String {
+    s.chars().map(|c| match c {
+        '"' => '\'',
+        c if c.is_control() => ' ',
+        c => c
+    }).collect()
+}
+
+fn xml_escape(s:&str) -> String {
+    s.replace('&',"&amp;").replace('"',"&quot;").replace('<',"&lt;").replace('>',"&gt;")
+}
+
+impl BmFont {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing={},{}\n",
+                              text_escape(&self.face),self.size,self.spacing,self.spacing);
+        out += &format!("common lineHeight={} base={} scaleW={} scaleH={} pages=1 packed=0\n",self.line_height,self.base,self.scale_w,self.scale_h);
+        out += &format!("page id=0 file=\"{}\"\n",text_escape(&self.page_file));
+        out += &format!("chars count={}\n",self.chars.len());
+        for c in self.chars.iter() {
+            out += &format!("char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} page=0 chnl=15\n",
+                            c.id,c.rect.x,c.rect.y,c.rect.width,c.rect.height,c.xoffset,c.yoffset,c.xadvance);
+        }
+        if !self.kernings.is_empty() {
+            out += &format!("kernings count={}\n",self.kernings.len());
+            for (first,second,amount) in self.kernings.iter() {
+                out += &format!("kerning first={} second={} amount={}\n",first,second,amount);
+            }
+        }
+        out
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\"?>\n<font>\n");
+        out += &format!("  <info face=\"{}\" size=\"{}\" bold=\"0\" italic=\"0\" charset=\"\" unicode=\"1\" stretchH=\"100\" smooth=\"1\" aa=\"1\" padding=\"0,0,0,0\" spacing=\"{},{}\"/>\n",
+                        xml_escape(&self.face),self.size,self.spacing,self.spacing);
+        out += &format!("  <common lineHeight=\"{}\" base=\"{}\" scaleW=\"{}\" scaleH=\"{}\" pages=\"1\" packed=\"0\"/>\n",
+                        self.line_height,self.base,self.scale_w,self.scale_h);
+        out += &format!("  <pages>\n    <page id=\"0\" file=\"{}\"/>\n  </pages>\n",xml_escape(&self.page_file));
+        out += &format!("  <chars count=\"{}\">\n",self.chars.len());
+        for c in self.chars.iter() {
+            out += &format!("    <char id=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" xoffset=\"{}\" yoffset=\"{}\" xadvance=\"{}\" page=\"0\" chnl=\"15\"/>\n",
+                            c.id,c.rect.x,c.rect.y,c.rect.width,c.rect.height,c.xoffset,c.yoffset,c.xadvance);
+        }
+        out += "  </chars>\n";
+        if !self.kernings.is_empty() {
+            out += &format!("  <kernings count=\"{}\">\n",self.kernings.len());
+            for (first,second,amount) in self.kernings.iter() {
+                out += &format!("    <kerning first=\"{}\" second=\"{}\" amount=\"{}\"/>\n",first,second,amount);
+            }
+            out += "  </kernings>\n";
+        }
+        out += "</font>\n";
+        out
+    }
+}
+
+struct RasterGlyph {
+    size_index:usize,
+    image:Option<RgbaImage>,
+    fnt_char:FntChar
+}
+
+/// Lists the char pairs named by the font's horizontal `kern` subtables, ordered like `chars`.
+/// Class based subtables can't be walked pair by pair, so they fall back to every pair.
+fn kerned_pairs<F:Font>(font:&F,kern:Option<ttf_parser::kern::Table>,chars:&[char]) -> Vec<(char,char)> {
+    let mut char_indices:HashMap<u16,Vec<usize>> = HashMap::new();
+    for (index,&c) in chars.iter().enumerate() {
+        char_indices.entry(font.glyph_id(c).0).or_default().push(index);
+    }
+    let mut pairs:Vec<(usize,usize)> = Vec::new();
+    for subtable in kern.into_iter().flat_map(|kern| kern.subtables) {
+        if !subtable.horizontal || subtable.variable {
+            continue;
+        }
+        match subtable.format {
+            ttf_parser::kern::Format::Format0(table) => {
+                for pair in table.pairs {
+                    if let (Some(firsts),Some(seconds)) = (char_indices.get(&pair.left().0),char_indices.get(&pair.right().0)) {
+                        for &first in firsts {
+                            pairs.extend(seconds.iter().map(|&second| (first,second)));
+                        }
+                    }
+                }
+            },
+            ttf_parser::kern::Format::Format2(_) | ttf_parser::kern::Format::Format3(_) => {
+                return chars.iter().flat_map(|&first| chars.iter().map(move |&second| (first,second))).collect();
+            },
+            _ => {}
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+    pairs.into_iter().map(|(first,second)| (chars[first],chars[second])).collect()
+}
+
+fn rasterize<F:Font>(font:&F,size:u32,size_index:usize,chars:&[char],kern_pairs:&[(char,char)],glyphs:&mut Vec<RasterGlyph>) -> BmFont {
+    let scaled = font.as_scaled(PxScale::from(size as f32));
+    let ascent = scaled.ascent();
+    for &c in chars {
+        let id = font.glyph_id(c);
+        if id.0 == 0 {
+            eprintln!("font has no glyph for {:?}", c);
+            continue;
+        }
+        let mut fnt_char = FntChar {id:c as u32,xadvance:scaled.h_advance(id).round() as i32,..FntChar::default()};
+        let glyph = id.with_scale_and_position(scaled.scale(),point(0.0,ascent));
+        let image = scaled.outline_glyph(glyph).map(|outlined| {
+            let bounds = outlined.px_bounds();
+            fnt_char.xoffset = bounds.min.x as i32;
+            fnt_char.yoffset = bounds.min.y as i32;
+            let mut image = RgbaImage::new(bounds.width() as u32,bounds.height() as u32);
+            outlined.draw(|x,y,coverage| {
+                image.put_pixel(x,y,Rgba([255,255,255,(coverage * 255.0).round() as u8]));
+            });
+            image
+        });
+        glyphs.push(RasterGlyph {size_index,image,fnt_char});
+    }
+    let mut kernings = Vec::new();
+    for &(first,second) in kern_pairs {
+        let amount = scaled.kern(font.glyph_id(first),font.glyph_id(second)).round() as i32;
+        if amount != 0 {
+            kernings.push((first as u32,second as u32,amount));
+        }
+    }
+    BmFont {
+        size,
+        line_height:(scaled.height() + scaled.line_gap()).ceil() as i32,
+        base:ascent.ceil() as i32,
+        kernings,
+        ..BmFont::default()
+    }
+}
+
+/// Packs the rasterized glyphs into one page and records each glyph's rect in its font.
+fn pack_glyphs(glyphs:Vec<RasterGlyph>,width:u32,height:u32,padding:u32,fonts:&mut [BmFont]) -> Result<RgbaImage,String> {
+    let mut out_image:RgbaImage = image::ImageBuffer::new(width,height);
+    let mut max_rect = max_rect::MaxRectsBinPack::new(width,height,false);
+    for glyph in glyphs {
+        let mut fnt_char = glyph.fnt_char;
+        if let Some(image) = glyph.image {
+            let (w,h) = image.dimensions();
+            let (rect,_) = max_rect.insert((w + padding * 2) as i32,(h + padding * 2) as i32,max_rect::FreeRectChoiceHeuristic::BestAreaFit);
+            if rect.height <= 0 {
+                return Err(String::from("font atlas too small"));
+            }
+            image::imageops::replace(&mut out_image,&image,rect.x as u32 + padding,rect.y as u32 + padding);
+            fnt_char.rect = max_rect::Rect {x:rect.x + padding as i32,y:rect.y + padding as i32,width:w as i32,height:h as i32};
+        }
+        fonts[glyph.size_index].chars.push(fnt_char);
+    }
+    Ok(out_image)
+}
+
+/// Rasterizes the configured characters at every size into one atlas page and writes
+/// `{out}.png` plus one BMFont descriptor per size (`{out}_{size}.fnt` / `.xml`).
+pub fn font_atlas_gen(cfg:FontAtlasConfig) -> Result<bool,String> {
+    let data = std::fs::read(&cfg.font_file).map_err(|_| String::from("can't read font file"))?;
+    let font = FontVec::try_from_vec(data).map_err(|_| String::from("invalid font file"))?;
+    let face = Path::new(&cfg.font_file).file_stem().and_then(|os_str| os_str.to_str()).unwrap_or("font").to_string();
+    let out_path = cfg.out_file.clone().unwrap_or_else(|| face.clone());
+    let page_file = Path::new(&out_path).file_name().and_then(|os_str| os_str.to_str()).unwrap_or("font").to_string() + ".png";
+
+    let mut chars:Vec<char> = Vec::new();
+    for c in cfg.chars.chars() {
+        if !chars.contains(&c) {
+            chars.push(c);
+        }
+    }
+    let kern = ttf_parser::Face::parse(font.font_data(),0).ok().and_then(|face| face.tables().kern);
+    let kern_pairs = kerned_pairs(&font,kern,&chars);
+    let mut glyphs = Vec::new();
+    let mut fonts = Vec::new();
+    for (size_index,size) in cfg.sizes.iter().enumerate() {
+        fonts.push(rasterize(&font,*size,size_index,&chars,&kern_pairs,&mut glyphs));
+    }
+
+    let out_image = pack_glyphs(glyphs,cfg.width,cfg.height,cfg.padding,&mut fonts)?;
+    out_image.save(out_path.clone() + ".png").map_err(|_| String::from("save image error"))?;
+
+    for mut fnt in fonts {
+        fnt.face = face.clone();
+        fnt.scale_w = cfg.width;
+        fnt.scale_h = cfg.height;
+        fnt.page_file = page_file.clone();
+        fnt.spacing = cfg.padding * 2;
+        let fnt_path = if cfg.sizes.len() > 1 { format!("{}_{}",out_path,fnt.size) } else { out_path.clone() };
+        if cfg.format != FntFormat::Xml {
+            std::fs::write(fnt_path.clone() + ".fnt",fnt.to_text()).map_err(|_| String::from("save fnt error"))?;
+        }
+        if cfg.format != FntFormat::Text {
+            std::fs::write(fnt_path + ".xml",fnt.to_xml()).map_err(|_| String::from("save fnt error"))?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BmFont,FntChar,kerned_pairs,pack_glyphs,rasterize};
+    use crate::max_rect::Rect;
+    use ab_glyph::{point,Font,GlyphId,Outline,OutlineCurve};
+
+    /// 16 units per pixel-size em: 'A' and 'V' are 8x10 boxes, ' ' has no outline, 'A''V' kerns by -2.
+    struct BoxFont;
+
+    impl Font for BoxFont {
+        fn units_per_em(&self) -> Option<f32> { Some(16.0) }
+        fn ascent_unscaled(&self) -> f32 { 12.0 }
+        fn descent_unscaled(&self) -> f32 { -4.0 }
+        fn line_gap_unscaled(&self) -> f32 { 0.0 }
+        fn glyph_id(&self,c:char) -> GlyphId {
+            GlyphId(match c { 'A' => 1,'V' => 2,' ' => 3,_ => 0 })
+        }
+        fn h_advance_unscaled(&self,_:GlyphId) -> f32 { 10.0 }
+        fn h_side_bearing_unscaled(&self,_:GlyphId) -> f32 { 1.0 }
+        fn v_advance_unscaled(&self,_:GlyphId) -> f32 { 16.0 }
+        fn v_side_bearing_unscaled(&self,_:GlyphId) -> f32 { 0.0 }
+        fn kern_unscaled(&self,first:GlyphId,second:GlyphId) -> f32 {
+            if (first.0,second.0) == (1,2) { -2.0 } else { 0.0 }
+        }
+        fn outline(&self,id:GlyphId) -> Option<Outline> {
+            if id.0 != 1 && id.0 != 2 {
+                return None;
+            }
+            let corners = [point(1.0,0.0),point(9.0,0.0),point(9.0,10.0),point(1.0,10.0)];
+            Some(Outline {
+                bounds:ab_glyph::Rect {min:point(1.0,10.0),max:point(9.0,0.0)},
+                curves:(0..4).map(|i| OutlineCurve::Line(corners[i],corners[(i + 1) % 4])).collect()
+            })
+        }
+        fn glyph_count(&self) -> usize { 4 }
+        fn codepoint_ids(&self) -> ab_glyph::CodepointIdIter<'_> { unimplemented!() }
+        fn glyph_raster_image2(&self,_:GlyphId,_:u16) -> Option<ab_glyph::v2::GlyphImage<'_>> { None }
+    }
+
+    /// An OpenType `kern` table with one horizontal format 0 subtable.
+    fn kern_table(pairs:&[(u16,u16,i16)]) -> Vec<u8> {
+        let mut data = vec![0,0,0,1,0,0];
+        data.extend_from_slice(&(14 + 6 * pairs.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[0,1]);
+        data.extend_from_slice(&(pairs.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[0;6]);
+        for (left,right,value) in pairs {
+            data.extend_from_slice(&left.to_be_bytes());
+            data.extend_from_slice(&right.to_be_bytes());
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_kerned_pairs() {
+        let data = kern_table(&[(1,2,-2),(2,9,-1)]);
+        let kern = ttf_parser::kern::Table::parse(&data);
+        assert_eq!(kerned_pairs(&BoxFont,kern,&['V',' ','A']),vec![('A','V')]);
+        assert!(kerned_pairs(&BoxFont,None,&['V','A']).is_empty());
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let mut glyphs = Vec::new();
+        let fnt = rasterize(&BoxFont,16,0,&['A','B',' ','V'],&[('A','V'),('V','A')],&mut glyphs);
+        assert_eq!((fnt.size,fnt.line_height,fnt.base),(16,16,12));
+        assert_eq!(fnt.kernings,vec![(65,86,-2)]);
+        // 'B' has no glyph and is dropped.
+        let ids:Vec<u32> = glyphs.iter().map(|glyph| glyph.fnt_char.id).collect();
+        assert_eq!(ids,vec![65,32,86]);
+        let a = &glyphs[0];
+        assert_eq!((a.fnt_char.xoffset,a.fnt_char.yoffset,a.fnt_char.xadvance),(1,2,10));
+        let image = a.image.as_ref().unwrap();
+        assert_eq!(image.dimensions(),(8,10));
+        assert!(image.pixels().all(|p| p.0 == [255,255,255,255]));
+        assert!(glyphs[1].image.is_none());
+
+        let mut fonts = vec![fnt];
+        let page = pack_glyphs(glyphs,32,32,1,&mut fonts).unwrap();
+        let chars = &fonts[0].chars;
+        assert_eq!(chars.len(),3);
+        let (a,space,v) = (&chars[0].rect,&chars[1].rect,&chars[2].rect);
+        assert_eq!((a.width,a.height,v.width,v.height),(8,10,8,10));
+        assert_eq!((space.width,space.height),(0,0));
+        // Padded rects keep a one pixel gap around each glyph.
+        assert!(a.x >= 1 && a.y >= 1 && v.x >= 1 && v.y >= 1);
+        assert!(a.x + a.width + 2 <= v.x || v.x + v.width + 2 <= a.x || a.y + a.height + 2 <= v.y || v.y + v.height + 2 <= a.y);
+        for rect in [a,v].iter() {
+            assert_eq!(page.get_pixel(rect.x as u32,rect.y as u32)[3],255);
+            assert_eq!(page.get_pixel((rect.x + rect.width - 1) as u32,(rect.y + rect.height - 1) as u32)[3],255);
+            assert_eq!(page.get_pixel(rect.x as u32 - 1,rect.y as u32)[3],0);
+        }
+
+        let mut glyphs = Vec::new();
+        let mut fonts = vec![rasterize(&BoxFont,16,0,&['A','V'],&[],&mut glyphs)];
+        assert!(pack_glyphs(glyphs,16,16,1,&mut fonts).is_err());
+    }
+
+    #[test]
+    fn test_fnt_xml() {
+        let fnt = BmFont {
+            face:String::from("Sans & \"Mono\""),
+            size:16,
+            spacing:2,
+            line_height:19,
+            base:15,
+            scale_w:64,
+            scale_h:32,
+            page_file:String::from("<sans>.png"),
+            chars:vec![FntChar {id:65,rect:Rect {x:1,y:2,width:9,height:11},xoffset:0,yoffset:4,xadvance:10}],
+            kernings:vec![]
+        };
+        let xml = fnt.to_xml();
+        let lines:Vec<&str> = xml.lines().collect();
+        assert_eq!(lines[0],"<?xml version=\"1.0\"?>");
+        assert_eq!(lines[2],"  <info face=\"Sans &amp; &quot;Mono&quot;\" size=\"16\" bold=\"0\" italic=\"0\" charset=\"\" unicode=\"1\" stretchH=\"100\" smooth=\"1\" aa=\"1\" padding=\"0,0,0,0\" spacing=\"2,2\"/>");
+        assert_eq!(lines[3],"  <common lineHeight=\"19\" base=\"15\" scaleW=\"64\" scaleH=\"32\" pages=\"1\" packed=\"0\"/>");
+        assert_eq!(lines[5],"    <page id=\"0\" file=\"&lt;sans&gt;.png\"/>");
+        assert_eq!(lines[7],"  <chars count=\"1\">");
+        assert_eq!(lines[8],"    <char id=\"65\" x=\"1\" y=\"2\" width=\"9\" height=\"11\" xoffset=\"0\" yoffset=\"4\" xadvance=\"10\" page=\"0\" chnl=\"15\"/>");
+        assert_eq!(lines[9],"  </chars>");
+        assert_eq!(lines[10],"</font>");
+    }
+
+    #[test]
+    fn test_fnt_text() {
+        let fnt = BmFont {
+            face:String::from("Sans"),
+            size:16,
+            spacing:2,
+            line_height:19,
+            base:15,
+            scale_w:64,
+            scale_h:64,
+            page_file:String::from("sans.png"),
+            chars:vec![FntChar {id:65,rect:Rect {x:1,y:2,width:9,height:11},xoffset:0,yoffset:4,xadvance:10}],
+            kernings:vec![(65,86,-1)]
+        };
+        let text = fnt.to_text();
+        let lines:Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0],"info face=\"Sans\" size=16 bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing=2,2");
+        assert_eq!(lines[1],"common lineHeight=19 base=15 scaleW=64 scaleH=64 pages=1 packed=0");
+        assert_eq!(lines[2],"page id=0 file=\"sans.png\"");
+        assert_eq!(lines[4],"char id=65 x=1 y=2 width=9 height=11 xoffset=0 yoffset=4 xadvance=10 page=0 chnl=15");
+        assert_eq!(lines[6],"kerning first=65 second=86 amount=-1");
+        assert!(fnt.to_xml().contains("<kerning first=\"65\" second=\"86\" amount=\"-1\"/>"));
+
+        let fnt = BmFont {face:String::from("My \"Font\"\nx"),page_file:String::from("fonts\\a.png"),..fnt};
+        assert_eq!(fnt.to_text().lines().next().unwrap().split(" size=").next().unwrap(),"info face=\"My 'Font' x\"");
+        assert!(fnt.to_text().contains("page id=0 file=\"fonts\\a.png\"\n"));
+    }
+}
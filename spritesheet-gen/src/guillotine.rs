@@ -0,0 +1,272 @@
+use crate::max_rect::Rect;
+use std::str::FromStr;
+
+/// Chooses which free rectangle a new rectangle is placed into.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum RectChoiceHeuristic {
+    BestAreaFit,
+    BestShortSideFit,
+    BestLongSideFit,
+    WorstAreaFit,
+    WorstShortSideFit,
+    WorstLongSideFit
+}
+
+impl FromStr for RectChoiceHeuristic {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "best-area" | "BestAreaFit" => Ok(RectChoiceHeuristic::BestAreaFit),
+            "best-short-side" | "BestShortSideFit" => Ok(RectChoiceHeuristic::BestShortSideFit),
+            "best-long-side" | "BestLongSideFit" => Ok(RectChoiceHeuristic::BestLongSideFit),
+            "worst-area" | "WorstAreaFit" => Ok(RectChoiceHeuristic::WorstAreaFit),
+            "worst-short-side" | "WorstShortSideFit" => Ok(RectChoiceHeuristic::WorstShortSideFit),
+            "worst-long-side" | "WorstLongSideFit" => Ok(RectChoiceHeuristic::WorstLongSideFit),
+            _ => Err(format!("unknown guillotine heuristic {}",s))
+        }
+    }
+}
+
+/// Chooses how the leftover of a used free rectangle is cut in two.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SplitHeuristic {
+    ShorterLeftoverAxis,
+    LongerLeftoverAxis,
+    MinimizeArea,
+    MaximizeArea,
+    ShorterAxis,
+    LongerAxis
+}
+
+impl FromStr for SplitHeuristic {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "shorter-leftover" | "ShorterLeftoverAxis" => Ok(SplitHeuristic::ShorterLeftoverAxis),
+            "longer-leftover" | "LongerLeftoverAxis" => Ok(SplitHeuristic::LongerLeftoverAxis),
+            "min-area" | "MinimizeArea" => Ok(SplitHeuristic::MinimizeArea),
+            "max-area" | "MaximizeArea" => Ok(SplitHeuristic::MaximizeArea),
+            "shorter-axis" | "ShorterAxis" => Ok(SplitHeuristic::ShorterAxis),
+            "longer-axis" | "LongerAxis" => Ok(SplitHeuristic::LongerAxis),
+            _ => Err(format!("unknown guillotine split {}",s))
+        }
+    }
+}
+
+/// Guillotine bin packer. Every placement cuts its free rectangle with one straight
+/// cut, so the final layout can always be separated by edge-to-edge cuts.
+#[derive(Debug,Clone)]
+pub struct GuillotineBinPack {
+    width:u32,
+    height:u32,
+    allow_rotations:bool,
+    used_rect:Vec<Rect>,
+    free_rect:Vec<Rect>
+}
+
+impl GuillotineBinPack {
+    pub fn new(width:u32,height:u32,rotations:bool) -> Self {
+        let mut guillotine = GuillotineBinPack {
+            width:0,
+            height:0,
+            allow_rotations:rotations,
+            used_rect:Vec::new(),
+            free_rect:Vec::new()
+        };
+        guillotine.init(width, height, rotations);
+        guillotine
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn free_rect(&self) -> &Vec<Rect> {
+        &self.free_rect
+    }
+
+    pub fn used_rect(&self) -> &Vec<Rect> {
+        &self.used_rect
+    }
+
+    pub fn set_allow_rotations(&mut self,rotations:bool) {
+        self.allow_rotations = rotations;
+    }
+
+    pub fn init(&mut self,width:u32,height:u32,rotations:bool) {
+        self.width = width;
+        self.height = height;
+        self.allow_rotations = rotations;
+        self.used_rect.clear();
+        self.free_rect.clear();
+        self.free_rect.push(Rect {x:0,y:0,width:width as i32,height:height as i32});
+    }
+
+    pub fn insert(&mut self,width:i32,height:i32,merge:bool,rect_choice:RectChoiceHeuristic,split_method:SplitHeuristic) -> Rect {
+        let (new_node,free_index) = self.find_position_for_new_node(width, height, rect_choice);
+        let free_index = match free_index {
+            Some(index) => index,
+            None => return new_node
+        };
+        let free_node = self.free_rect.remove(free_index);
+        self.split_free_rect_by_heuristic(&free_node, &new_node, split_method);
+        if merge {
+            self.merge_free_list();
+        }
+        self.used_rect.push(new_node.clone());
+        new_node
+    }
+
+    fn score_by_heuristic(width:i32,height:i32,free_rect:&Rect,rect_choice:RectChoiceHeuristic) -> i32 {
+        let area_fit = free_rect.width * free_rect.height - width * height;
+        let short_side_fit = i32::min(i32::abs(free_rect.width - width),i32::abs(free_rect.height - height));
+        let long_side_fit = i32::max(i32::abs(free_rect.width - width),i32::abs(free_rect.height - height));
+        match rect_choice {
+            RectChoiceHeuristic::BestAreaFit => area_fit,
+            RectChoiceHeuristic::BestShortSideFit => short_side_fit,
+            RectChoiceHeuristic::BestLongSideFit => long_side_fit,
+            RectChoiceHeuristic::WorstAreaFit => -area_fit,
+            RectChoiceHeuristic::WorstShortSideFit => -short_side_fit,
+            RectChoiceHeuristic::WorstLongSideFit => -long_side_fit
+        }
+    }
+
+    fn find_position_for_new_node(&self,width:i32,height:i32,rect_choice:RectChoiceHeuristic) -> (Rect,Option<usize>) {
+        let mut best_node = Rect::default();
+        let mut best_score = i32::MAX;
+        let mut best_index = None;
+        for (i,free_rect) in self.free_rect.iter().enumerate() {
+            if width == free_rect.width && height == free_rect.height {
+                return (Rect {x:free_rect.x,y:free_rect.y,width,height},Some(i));
+            }
+            if self.allow_rotations && height == free_rect.width && width == free_rect.height {
+                return (Rect {x:free_rect.x,y:free_rect.y,width:height,height:width},Some(i));
+            }
+            if width <= free_rect.width && height <= free_rect.height {
+                let score = Self::score_by_heuristic(width, height, free_rect, rect_choice);
+                if score < best_score {
+                    best_node = Rect {x:free_rect.x,y:free_rect.y,width,height};
+                    best_score = score;
+                    best_index = Some(i);
+                }
+            }
+            if self.allow_rotations && height <= free_rect.width && width <= free_rect.height {
+                let score = Self::score_by_heuristic(height, width, free_rect, rect_choice);
+                if score < best_score {
+                    best_node = Rect {x:free_rect.x,y:free_rect.y,width:height,height:width};
+                    best_score = score;
+                    best_index = Some(i);
+                }
+            }
+        }
+        (best_node,best_index)
+    }
+
+    fn split_free_rect_by_heuristic(&mut self,free_rect:&Rect,placed_rect:&Rect,method:SplitHeuristic) {
+        let w = free_rect.width - placed_rect.width;
+        let h = free_rect.height - placed_rect.height;
+        let split_horizontal = match method {
+            SplitHeuristic::ShorterLeftoverAxis => w <= h,
+            SplitHeuristic::LongerLeftoverAxis => w > h,
+            SplitHeuristic::MinimizeArea => placed_rect.width * h > w * placed_rect.height,
+            SplitHeuristic::MaximizeArea => placed_rect.width * h <= w * placed_rect.height,
+            SplitHeuristic::ShorterAxis => free_rect.width <= free_rect.height,
+            SplitHeuristic::LongerAxis => free_rect.width > free_rect.height
+        };
+        self.split_free_rect_along_axis(free_rect, placed_rect, split_horizontal);
+    }
+
+    fn split_free_rect_along_axis(&mut self,free_rect:&Rect,placed_rect:&Rect,split_horizontal:bool) {
+        let mut bottom = Rect {
+            x:free_rect.x,
+            y:free_rect.y + placed_rect.height,
+            width:0,
+            height:free_rect.height - placed_rect.height
+        };
+        let mut right = Rect {
+            x:free_rect.x + placed_rect.width,
+            y:free_rect.y,
+            width:free_rect.width - placed_rect.width,
+            height:0
+        };
+        if split_horizontal {
+            bottom.width = free_rect.width;
+            right.height = placed_rect.height;
+        } else {
+            bottom.width = placed_rect.width;
+            right.height = free_rect.height;
+        }
+        if bottom.width > 0 && bottom.height > 0 {
+            self.free_rect.push(bottom);
+        }
+        if right.width > 0 && right.height > 0 {
+            self.free_rect.push(right);
+        }
+    }
+
+    /// Joins free rectangles that share a full edge, which keeps the free list short
+    /// and lets larger rectangles fit later.
+    fn merge_free_list(&mut self) {
+        let mut i = 0;
+        while i < self.free_rect.len() {
+            let mut j = i + 1;
+            while j < self.free_rect.len() {
+                let other = self.free_rect[j].clone();
+                let rect = &mut self.free_rect[i];
+                let merged = if rect.width == other.width && rect.x == other.x {
+                    if rect.y == other.y + other.height {
+                        rect.y -= other.height;
+                        rect.height += other.height;
+                        true
+                    } else if rect.y + rect.height == other.y {
+                        rect.height += other.height;
+                        true
+                    } else {
+                        false
+                    }
+                } else if rect.height == other.height && rect.y == other.y {
+                    if rect.x == other.x + other.width {
+                        rect.x -= other.width;
+                        rect.width += other.width;
+                        true
+                    } else if rect.x + rect.width == other.x {
+                        rect.width += other.width;
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                };
+                if merged {
+                    self.free_rect.remove(j);
+                } else {
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuillotineBinPack,RectChoiceHeuristic,SplitHeuristic};
+
+    #[test]
+    fn test_merge_free_list() {
+        // The two leftovers below the 40x40 and 60x40 rects share an edge and merge into one 100x60 rect.
+        for &(merge,free_count) in [(false,2),(true,1)].iter() {
+            let mut guillotine = GuillotineBinPack::new(100,100,false);
+            guillotine.insert(40, 40, merge, RectChoiceHeuristic::BestAreaFit, SplitHeuristic::LongerLeftoverAxis);
+            guillotine.insert(60, 40, merge, RectChoiceHeuristic::BestAreaFit, SplitHeuristic::LongerLeftoverAxis);
+            assert_eq!(guillotine.free_rect().len(),free_count);
+            let rect = guillotine.insert(100, 60, merge, RectChoiceHeuristic::BestAreaFit, SplitHeuristic::LongerLeftoverAxis);
+            assert_eq!(rect.height > 0,merge);
+        }
+    }
+}
@@ -1,19 +1,184 @@
 pub mod max_rect;
+pub mod anneal;
+pub mod font;
+pub mod guillotine;
+pub mod packer;
+pub mod psd;
+pub mod shelf;
+pub mod skyline;
+pub mod slice;
+pub mod unpack;
+use std::collections::HashMap;
 use std::fs::{self};
-use image::{RgbaImage};
+use image::{Pixel,Rgba,RgbaImage};
+use rayon::prelude::*;
 use serde_json::{Value,Map,Number};
-use std::path::{Path};
+use std::path::{Path,PathBuf};
 
+type WriteDescFn = Box<dyn Fn(&String,&SpriteSheetGenConfig,&Vec<PackedSprite>)>;
+
+/// A decoded input image waiting to be packed.
+pub struct SpriteImage {
+    pub name:String,
+    pub image:RgbaImage,
+    /// Position of the sprite on its source canvas, e.g. a PSD layer offset.
+    pub offset:Option<(i32,i32)>
+}
+
+/// A sprite placed in the atlas, as handed to the descriptor writer.
+pub struct PackedSprite {
+    pub name:String,
+    pub rect:max_rect::Rect,
+    /// The sprite is stored rotated 90 degrees clockwise.
+    pub rotated:bool,
+    pub offset:Option<(i32,i32)>
+}
+
+/// Summary of a `sprite_sheet_gen` run.
+#[derive(Debug,Clone,Default)]
+pub struct PackStats {
+    pub sprites_packed:usize,
+    /// Sprites that didn't fit on any page and were left out.
+    pub sprites_unplaced:usize,
+    pub pages:usize,
+    /// Sprite pixels, padding excluded.
+    pub used_pixels:u64,
+    /// Page pixels not covered by a sprite, padding included.
+    pub wasted_pixels:u64,
+    /// `used_pixels` over the total page area.
+    pub occupancy:f32,
+    /// The packer and sort order `try_all` picked.
+    pub best_packer:Option<(String,packer::SortOrder)>,
+    /// The layout before and after the `optimize` search; the better of the two is kept.
+    pub optimized:Option<(LayoutSummary,LayoutSummary)>
+}
+
+/// How a candidate layout scored while packing, before any pixels are copied.
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct LayoutSummary {
+    pub pages:usize,
+    pub unplaced:usize,
+    /// Placed area over the bounding box of each page's placements.
+    pub occupancy:f64
+}
+
+/// How sprites are put into groups that `sprite_sheet_gen` keeps on a single page.
+pub enum SpriteGrouping {
+    /// Each subfolder of the input dir is a group, and its images are packed too.
+    /// With a sprite list, files are grouped by the folder they are in.
+    Subfolder,
+    /// Sprites named alike up to the last `separator` form a group, e.g. `walk_01` and `walk_02`.
+    Prefix(char),
+    /// Sprite name to group name. Sprites that aren't listed are packed on their own.
+    Manifest(HashMap<String,String>)
+}
+
+impl SpriteGrouping {
+    /// Reads a JSON manifest that maps each group name to a list of sprite names.
+    pub fn load_manifest(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read group manifest {:?}: {}", path, err))?;
+        let manifest: Value = serde_json::from_str(&text).map_err(|err| format!("bad group manifest {:?}: {}", path, err))?;
+        let mut groups = HashMap::new();
+        for (group, names) in manifest.as_object().ok_or_else(|| String::from("group manifest must be an object"))? {
+            for name in names.as_array().ok_or_else(|| format!("group {} must list sprite names", group))? {
+                let name = name.as_str().ok_or_else(|| format!("group {} must list sprite names", group))?;
+                groups.insert(String::from(name), group.clone());
+            }
+        }
+        Ok(SpriteGrouping::Manifest(groups))
+    }
+
+    fn group_name(&self, sprite_name: &str, folder: Option<&String>) -> Option<String> {
+        match self {
+            SpriteGrouping::Subfolder => folder.cloned(),
+            SpriteGrouping::Prefix(separator) => sprite_name.rfind(*separator).map(|end| String::from(&sprite_name[..end])),
+            SpriteGrouping::Manifest(groups) => groups.get(sprite_name).cloned()
+        }
+    }
+}
+
+/// Settings of a single sprite that replace the global ones.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct SpriteOverride {
+    /// Whether the sprite may be rotated, e.g. never for tiled backgrounds or text.
+    pub rotation:Option<bool>,
+    /// Padding around the sprite, e.g. more room for a glow effect. The left and top padding grow
+    /// to a multiple of the alignment.
+    pub padding:Option<u32>
+}
+
+impl SpriteOverride {
+    /// Reads a JSON manifest such as `{"bg_tile": {"rotation": false}, "button": {"padding": 8}}`.
+    pub fn load_manifest(path: &Path) -> Result<HashMap<String, SpriteOverride>, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read override manifest {:?}: {}", path, err))?;
+        let manifest: Value = serde_json::from_str(&text).map_err(|err| format!("bad override manifest {:?}: {}", path, err))?;
+        let mut overrides = HashMap::new();
+        for (name, settings) in manifest.as_object().ok_or_else(|| String::from("override manifest must be an object"))? {
+            let rotation = match settings.get("rotation") {
+                Some(value) => Some(value.as_bool().ok_or_else(|| format!("rotation of {} must be a bool", name))?),
+                None => None
+            };
+            let padding = match settings.get("padding") {
+                Some(value) => Some(value.as_u64().ok_or_else(|| format!("padding of {} must be a number", name))? as u32),
+                None => None
+            };
+            overrides.insert(name.clone(), SpriteOverride { rotation, padding });
+        }
+        Ok(overrides)
+    }
+}
+
+/// How the height of a strip packed atlas is rounded up.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum HeightRounding {
+    /// Exactly the height the sprites need.
+    Tight,
+    PowerOfTwo,
+    Multiple(u32)
+}
+
+impl HeightRounding {
+    fn round(self, height: u32) -> u32 {
+        match self {
+            HeightRounding::Tight => height,
+            HeightRounding::PowerOfTwo => height.next_power_of_two(),
+            HeightRounding::Multiple(n) => align_up(height, u32::max(n, 1))
+        }
+    }
+}
 
 pub struct SpriteSheetGenConfig {
     dir:String,
     width:u32,
     height:u32,
     padding:u32,
+    border_padding:Option<u32>,
+    shape_padding:Option<u32>,
     is_rotation:bool,
-    write_desc_fn:Box<dyn Fn(&String,&SpriteSheetGenConfig,&Vec<(String,max_rect::Rect)>)>,
+    write_desc_fn:WriteDescFn,
     out_file:Option<String>,
     sprite_list:Vec<String>,
+    sliced_sheets:Vec<(String,slice::SheetSlice)>,
+    packer:packer::PackerKind,
+    heuristic:max_rect::FreeRectChoiceHeuristic,
+    skyline_heuristic:skyline::SkylineHeuristic,
+    use_waste_map:bool,
+    guillotine_choice:guillotine::RectChoiceHeuristic,
+    guillotine_split:guillotine::SplitHeuristic,
+    guillotine_merge:bool,
+    shelf_heuristic:shelf::ShelfHeuristic,
+    sort_order:packer::SortOrder,
+    max_pages:usize,
+    try_all:bool,
+    optimize:Option<anneal::AnnealConfig>,
+    reserved_regions:Vec<max_rect::Rect>,
+    pinned_sprites:Vec<(String,u32,u32)>,
+    white_block:Option<(String,u32)>,
+    alignment:u32,
+    grouping:Option<SpriteGrouping>,
+    strip:Option<HeightRounding>,
+    page_sizes:Vec<(u32,u32)>,
+    overrides:HashMap<String,SpriteOverride>,
 }
 
 impl Default for SpriteSheetGenConfig {
@@ -24,9 +189,32 @@ impl Default for SpriteSheetGenConfig {
             height: 1024,
             is_rotation: false,
             padding:2,
+            border_padding:None,
+            shape_padding:None,
             write_desc_fn:Box::new(write_default_json),
             out_file: None,
-            sprite_list:vec![]
+            sprite_list:vec![],
+            sliced_sheets:vec![],
+            packer:packer::PackerKind::MaxRects,
+            heuristic:max_rect::FreeRectChoiceHeuristic::BestAreaFit,
+            skyline_heuristic:skyline::SkylineHeuristic::BottomLeft,
+            use_waste_map:false,
+            guillotine_choice:guillotine::RectChoiceHeuristic::BestShortSideFit,
+            guillotine_split:guillotine::SplitHeuristic::ShorterLeftoverAxis,
+            guillotine_merge:true,
+            shelf_heuristic:shelf::ShelfHeuristic::BestHeightFit,
+            sort_order:packer::SortOrder::None,
+            max_pages:1,
+            try_all:false,
+            optimize:None,
+            reserved_regions:vec![],
+            pinned_sprites:vec![],
+            white_block:None,
+            alignment:1,
+            grouping:None,
+            strip:None,
+            page_sizes:vec![],
+            overrides:HashMap::new()
         }
     }
 }
@@ -45,6 +233,14 @@ impl SpriteSheetGenConfig {
     pub fn set_padding(&mut self,padding:u32) {
         self.padding = padding;
     }
+    /// Space kept clear along the atlas edges. Defaults to `padding`.
+    pub fn set_border_padding(&mut self,border_padding:u32) {
+        self.border_padding = Some(border_padding);
+    }
+    /// Space kept between sprites. Defaults to twice `padding`, the gap left by padding both sprites.
+    pub fn set_shape_padding(&mut self,shape_padding:u32) {
+        self.shape_padding = Some(shape_padding);
+    }
     pub fn set_width(&mut self,w:u32) {
         self.width = w;
     }
@@ -59,48 +255,184 @@ impl SpriteSheetGenConfig {
     pub fn set_sprite_list(&mut self, sprite_list: Vec<String>) {
         self.sprite_list = sprite_list;
     }
+
+    pub fn set_packer(&mut self, packer: packer::PackerKind) {
+        self.packer = packer;
+    }
+
+    /// Free rect choice rule of the MaxRects packer.
+    pub fn set_heuristic(&mut self, heuristic: max_rect::FreeRectChoiceHeuristic) {
+        self.heuristic = heuristic;
+    }
+
+    pub fn set_skyline_heuristic(&mut self, heuristic: skyline::SkylineHeuristic) {
+        self.skyline_heuristic = heuristic;
+    }
+
+    pub fn set_shelf_heuristic(&mut self, heuristic: shelf::ShelfHeuristic) {
+        self.shelf_heuristic = heuristic;
+    }
+
+    /// Lets the skyline and shelf packers reuse the gaps they leave behind.
+    pub fn set_use_waste_map(&mut self, use_waste_map: bool) {
+        self.use_waste_map = use_waste_map;
+    }
+
+    pub fn set_guillotine_heuristic(&mut self, choice: guillotine::RectChoiceHeuristic, split: guillotine::SplitHeuristic) {
+        self.guillotine_choice = choice;
+        self.guillotine_split = split;
+    }
+
+    /// Merges neighbouring free rects of the guillotine packer after every placement.
+    pub fn set_guillotine_merge(&mut self, merge: bool) {
+        self.guillotine_merge = merge;
+    }
+
+    pub fn set_sort_order(&mut self, sort_order: packer::SortOrder) {
+        self.sort_order = sort_order;
+    }
+
+    /// Allows up to `max_pages` atlas pages. With more than one page, the outputs are named `{out}_{page}`.
+    pub fn set_max_pages(&mut self, max_pages: usize) {
+        self.max_pages = usize::max(max_pages, 1);
+    }
+
+    /// Packs with every packer, heuristic and sort order and keeps the layout with the fewest pages
+    /// and the highest occupancy. The configured packer and sort order are ignored.
+    pub fn set_try_all(&mut self, try_all: bool) {
+        self.try_all = try_all;
+    }
+
+    /// Searches for a better insertion order and rotation of each sprite with simulated annealing,
+    /// starting from the configured (or try-all) layout.
+    pub fn set_optimize(&mut self, optimize: anneal::AnnealConfig) {
+        self.optimize = Some(optimize);
+    }
+
+    /// Keeps `rect` empty on every page, with the shape padding between it and nearby sprites.
+    /// Needs the MaxRects packer.
+    pub fn add_reserved_region(&mut self, rect: max_rect::Rect) {
+        self.reserved_regions.push(rect);
+    }
+
+    /// Places the sprite named `name` unrotated with its top-left pixel at (`x`,`y`) on the first page.
+    /// Needs the MaxRects packer.
+    pub fn pin_sprite(&mut self, name: &str, x: u32, y: u32) {
+        self.pinned_sprites.push((String::from(name), x, y));
+    }
+
+    /// Adds an opaque white `size`x`size` sprite called `name` to every page, so untextured quads
+    /// can be drawn from the atlas. Its center UV is written to the descriptor meta as `white_uv`.
+    pub fn set_white_block(&mut self, name: &str, size: u32) {
+        self.white_block = Some((String::from(name), size));
+    }
+
+    /// Starts every placement on a multiple of `alignment` pixels and pads it to a multiple of it,
+    /// rounding the page size up to match, so block compressed sprites don't bleed into each other.
+    /// Needs the MaxRects packer.
+    pub fn set_alignment(&mut self, alignment: u32) {
+        self.alignment = u32::max(alignment, 1);
+    }
+
+    /// Packs each group of sprites onto one page, opening a new page when a group doesn't fit,
+    /// so an animation or a UI screen is drawn from a single texture.
+    pub fn set_grouping(&mut self, grouping: SpriteGrouping) {
+        self.grouping = Some(grouping);
+    }
+
+    /// Keeps the width fixed and grows the height until every sprite fits on a single page,
+    /// instead of packing into the configured height.
+    pub fn set_strip_packing(&mut self, rounding: HeightRounding) {
+        self.strip = Some(rounding);
+    }
+
+    /// Smaller page sizes the last page may shrink to, e.g. 1024, 512 and 256 squares below a
+    /// 2048 page. The last page takes the smallest one that holds its sprites; every other page
+    /// keeps the configured size.
+    pub fn set_page_sizes(&mut self, page_sizes: Vec<(u32, u32)>) {
+        self.page_sizes = page_sizes;
+    }
+
+    /// Replaces the rotation or padding setting for the sprite called `name`.
+    pub fn set_sprite_override(&mut self, name: &str, sprite_override: SpriteOverride) {
+        self.overrides.insert(String::from(name), sprite_override);
+    }
+
+    fn border_and_shape_padding(&self) -> (u32, u32) {
+        (self.border_padding.unwrap_or(self.padding), self.shape_padding.unwrap_or(self.padding * 2))
+    }
+
+    // Space the sprite takes before it (left and top) and after it (right and bottom) when packed.
+    // Shape padding only goes after sprites, so it never lands against the leading atlas edges.
+    // A leading margin is rounded up to the alignment to keep the sprite itself on the grid.
+    fn margins_of(&self, name: &str) -> (u32, u32) {
+        let (_, shape_padding) = self.border_and_shape_padding();
+        match self.overrides.get(name).and_then(|o| o.padding) {
+            Some(padding) => (align_up(padding, self.alignment), u32::max(padding, shape_padding)),
+            None => (0, shape_padding)
+        }
+    }
+
+    // Atlas position of the packing area and its size. The area starts at the border padding and
+    // runs past the opposite border by the shape padding, which the last sprite of a row only uses
+    // as its trailing gap.
+    fn pack_area(&self) -> (u32, u32, u32) {
+        let (border_padding, shape_padding) = self.border_and_shape_padding();
+        let origin = align_up(border_padding, self.alignment);
+        let size = |atlas_size: u32| atlas_size.saturating_add(shape_padding).saturating_sub(border_padding + origin);
+        (origin, size(self.width), size(self.height))
+    }
+
+    fn rotation_of(&self, name: &str) -> bool {
+        self.overrides.get(name).and_then(|o| o.rotation).unwrap_or(self.is_rotation)
+    }
+
+    // Packers are built with rotation when any sprite may rotate, and it is turned off per sprite.
+    fn packer_rotation(&self) -> bool {
+        self.is_rotation || self.overrides.values().any(|o| o.rotation == Some(true))
+    }
+
+    /// Slices a legacy uniform-grid sheet into one sprite per non-empty cell.
+    /// The sheet itself is not packed when it also lives in `dir`.
+    pub fn add_grid_sheet(&mut self, path: &str, grid: slice::GridSlice) {
+        self.sliced_sheets.push((String::from(path), slice::SheetSlice::Grid(grid)));
+    }
+
+    /// Extracts every connected opaque region of a large canvas as its own sprite.
+    /// The canvas itself is not packed when it also lives in `dir`.
+    pub fn add_island_sheet(&mut self, path: &str, islands: slice::IslandSlice) {
+        self.sliced_sheets.push((String::from(path), slice::SheetSlice::Islands(islands)));
+    }
 }
 
-fn process_image(
-    path: &Path,
-    out_image: &mut RgbaImage,
-    max_rect: &mut max_rect::MaxRectsBinPack,
-    padding: u32,
-    writed_list: &mut Vec<(String, max_rect::Rect)>
-) {
+fn load_sprites(path: &Path, sprites: &mut Vec<SpriteImage>) {
+    let is_psd = path
+        .extension()
+        .and_then(|os_str| os_str.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("psd"))
+        .unwrap_or(false);
+    if is_psd {
+        match psd::load_layers(path) {
+            Ok(layers) => {
+                for layer in layers {
+                    sprites.push(SpriteImage {
+                        name: layer.name,
+                        image: layer.image,
+                        offset: Some((layer.left, layer.top))
+                    });
+                }
+            }
+            Err(err) => eprintln!("can't open psd {:?}: {}", path, err)
+        }
+        return;
+    }
     if let Ok(img) = image::open(path) {
-        let mut rgba_image = img.to_rgba();
-        let (w, h) = rgba_image.dimensions();
-        let padding_w = w + padding * 2;
-        let padding_h = h + padding * 2;
-        let mut insert_rect = max_rect.insert(
-            padding_w as i32,
-            padding_h as i32,
-            max_rect::FreeRectChoiceHeuristic::BestAreaFit
-        );
-        if insert_rect.height <= 0 {
-            eprintln!("image to small, can't place {:?}", path);
-            return;
-        }
-        if insert_rect.width != padding_w as i32 {
-            rgba_image = image::imageops::rotate90(&rgba_image);
-        }
-        image::imageops::overlay(
-            out_image,
-            &rgba_image,
-            insert_rect.x as u32 + padding,
-            insert_rect.y as u32 + padding
-        );
         let may_file_name = path
             .file_stem()
             .and_then(|os_str| os_str.to_str())
-            .map(|s| String::from(s));
+            .map(String::from);
         if let Some(file_name) = may_file_name {
-            insert_rect.x += padding as i32;
-            insert_rect.y += padding as i32;
-            insert_rect.width -= (padding as i32) * 2;
-            insert_rect.height -= (padding as i32) * 2;
-            writed_list.push((file_name, insert_rect));
+            sprites.push(SpriteImage { name: file_name, image: img.to_rgba(), offset: None });
         } else {
             eprintln!("can't get filename {:?}", path);
         }
@@ -109,51 +441,771 @@ fn process_image(
     }
 }
 
-pub fn sprite_sheet_gen(cfg:SpriteSheetGenConfig) -> Result<bool,String> {
-    let mut out_image:RgbaImage = image::ImageBuffer::new(cfg.width,cfg.height);
-    let mut max_rect = max_rect::MaxRectsBinPack::new(cfg.width, cfg.height,cfg.is_rotation);
-    let mut writed_list:Vec<(String,max_rect::Rect)> = Vec::new();
-    
+fn load_sliced_sheet(path: &Path, slicer: &slice::SheetSlice, sprites: &mut Vec<SpriteImage>) {
+    let may_file_name = path.file_stem().and_then(|os_str| os_str.to_str());
+    match (image::open(path), may_file_name) {
+        (Ok(img), Some(file_name)) => sprites.extend(slicer.slice(file_name, &img.to_rgba())),
+        _ => eprintln!("can't open sheet: {:?}", path)
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b
+    }
+}
+
+fn create_packer(cfg: &SpriteSheetGenConfig) -> packer::Packer {
+    let (_, width, height) = cfg.pack_area();
+    match cfg.packer {
+        packer::PackerKind::MaxRects => packer::Packer::MaxRects(
+            max_rect::MaxRectsBinPack::new(width, height, cfg.packer_rotation()),
+            cfg.heuristic
+        ),
+        packer::PackerKind::Skyline => packer::Packer::Skyline(
+            skyline::SkylineBinPack::new(width, height, cfg.packer_rotation(), cfg.use_waste_map),
+            cfg.skyline_heuristic
+        ),
+        packer::PackerKind::Guillotine => packer::Packer::Guillotine(
+            guillotine::GuillotineBinPack::new(width, height, cfg.packer_rotation()),
+            cfg.guillotine_choice,
+            cfg.guillotine_split,
+            cfg.guillotine_merge
+        ),
+        packer::PackerKind::Shelf => packer::Packer::Shelf(
+            shelf::ShelfBinPack::new(width, height, cfg.packer_rotation(), cfg.use_waste_map),
+            cfg.shelf_heuristic
+        )
+    }
+}
+
+// An empty packer with the heuristics of `packer`, sized to the config's packing area.
+fn resize_packer(cfg: &SpriteSheetGenConfig, packer: &packer::Packer) -> packer::Packer {
+    let (_, width, height) = cfg.pack_area();
+    match packer {
+        packer::Packer::MaxRects(_, method) => packer::Packer::MaxRects(
+            max_rect::MaxRectsBinPack::new(width, height, cfg.packer_rotation()),
+            *method
+        ),
+        packer::Packer::Skyline(_, method) => packer::Packer::Skyline(
+            skyline::SkylineBinPack::new(width, height, cfg.packer_rotation(), cfg.use_waste_map),
+            *method
+        ),
+        packer::Packer::Guillotine(_, rect_choice, split_method, merge) => packer::Packer::Guillotine(
+            guillotine::GuillotineBinPack::new(width, height, cfg.packer_rotation()),
+            *rect_choice,
+            *split_method,
+            *merge
+        ),
+        packer::Packer::Shelf(_, method) => packer::Packer::Shelf(
+            shelf::ShelfBinPack::new(width, height, cfg.packer_rotation(), cfg.use_waste_map),
+            *method
+        )
+    }
+}
+
+/// The result of packing sprite sizes, before any pixels are copied.
+struct Layout {
+    pages:Vec<packer::Packer>,
+    /// Padded rect of the white block on each page.
+    white_blocks:Vec<Option<max_rect::Rect>>,
+    /// Page index, padded rect and rotation of every sprite, in input order.
+    placements:Vec<Option<(usize,max_rect::Rect,bool)>>
+}
+
+impl Layout {
+    fn unplaced(&self) -> usize {
+        self.placements.iter().filter(|placement| placement.is_none()).count()
+    }
+
+    /// Placed area divided by the area of the bounding box of each page's placements.
+    fn occupancy(&self) -> f64 {
+        let mut used_area = 0u64;
+        let mut bounds_area = 0u64;
+        for page in self.pages.iter() {
+            let (mut right,mut bottom) = (0,0);
+            for rect in page.used_rect() {
+                used_area += rect.width as u64 * rect.height as u64;
+                right = i32::max(right, rect.x + rect.width);
+                bottom = i32::max(bottom, rect.y + rect.height);
+            }
+            bounds_area += right as u64 * bottom as u64;
+        }
+        if bounds_area == 0 { 0.0 } else { used_area as f64 / bounds_area as f64 }
+    }
+
+    fn is_better_than(&self,other:&Layout) -> bool {
+        let (unplaced,other_unplaced) = (self.unplaced(),other.unplaced());
+        if unplaced != other_unplaced {
+            return unplaced < other_unplaced;
+        }
+        if self.pages.len() != other.pages.len() {
+            return self.pages.len() < other.pages.len();
+        }
+        self.occupancy() > other.occupancy()
+    }
+
+    fn summary(&self) -> LayoutSummary {
+        LayoutSummary { pages: self.pages.len(), unplaced: self.unplaced(), occupancy: self.occupancy() }
+    }
+
+    // Ranks layouts the same way as `is_better_than`, as a number for the optimizer.
+    fn cost(&self) -> f64 {
+        self.unplaced() as f64 * 1e6 + self.pages.len() as f64 * 1e3 + 1.0 - self.occupancy()
+    }
+}
+
+/// How pages are set up before packing.
+struct PageTemplate {
+    /// An empty page with the reserved regions taken.
+    prototype:packer::Packer,
+    /// Sprite index and padded rect of the sprites pinned to the first page.
+    pins:Vec<(usize,max_rect::Rect)>,
+    /// Padded size of the white block placed on every page.
+    white_block:Option<u32>,
+    /// Sprite indices that must share a page.
+    groups:Vec<Vec<usize>>,
+    /// Whether each sprite may be rotated. When empty the packer's own setting is used.
+    rotations:Vec<bool>,
+    max_pages:usize
+}
+
+impl PageTemplate {
+    fn new(cfg: &SpriteSheetGenConfig, mut prototype: packer::Packer, pins: Vec<(usize, max_rect::Rect)>, groups: Vec<Vec<usize>>, rotations: Vec<bool>) -> Result<Self, String> {
+        if !prototype.set_alignment(cfg.alignment) {
+            return Err(format!("can't align placements with the {} packer", prototype));
+        }
+        let (origin, width, height) = cfg.pack_area();
+        let (_, shape_padding) = cfg.border_and_shape_padding();
+        for rect in cfg.reserved_regions.iter() {
+            // Sprites before a region bring their own trailing gap; the ones after it get it here.
+            let moved = max_rect::Rect {
+                x: rect.x - origin as i32,
+                y: rect.y - origin as i32,
+                width: rect.width + shape_padding as i32,
+                height: rect.height + shape_padding as i32
+            };
+            let clipped = clip_rect(&moved, width, height);
+            // Parts inside the border are never packed anyway.
+            if clipped.width <= 0 || clipped.height <= 0 {
+                continue;
+            }
+            if !prototype.reserve(clipped) {
+                return Err(format!("can't reserve region {:?} with the {} packer", rect, prototype));
+            }
+        }
+        let white_block = cfg.white_block.as_ref().map(|(name, size)| {
+            let (lead, trail) = cfg.margins_of(name);
+            size + lead + trail
+        });
+        let template = PageTemplate { prototype, pins, white_block, groups, rotations, max_pages: cfg.max_pages };
+        for page_index in 0..usize::min(template.max_pages, 2) {
+            template.open_page(page_index)?;
+        }
+        Ok(template)
+    }
+
+    fn insert_sprite(&self, page: &mut packer::Packer, index: usize, size: (u32, u32)) -> (max_rect::Rect, bool) {
+        if let Some(&rotation) = self.rotations.get(index) {
+            page.set_allow_rotations(rotation);
+        }
+        page.insert(size.0 as i32, size.1 as i32)
+    }
+
+    // Every page after the first one is the same, so a template that opens the first two pages opens them all.
+    fn open_page(&self, page_index: usize) -> Result<(packer::Packer, Option<max_rect::Rect>), String> {
+        let mut page = self.prototype.clone();
+        if page_index == 0 {
+            for (_, rect) in self.pins.iter() {
+                let clipped = clip_rect(rect, page.width(), page.height());
+                if !page.reserve(clipped) {
+                    return Err(format!("can't pin a sprite at {:?}", rect));
+                }
+            }
+        }
+        let white_block = match self.white_block {
+            Some(size) => {
+                let (rect, _) = page.insert(size as i32, size as i32);
+                if rect.height <= 0 {
+                    return Err(String::from("no room for the white block"));
+                }
+                Some(rect)
+            }
+            None => None
+        };
+        Ok((page, white_block))
+    }
+}
+
+fn align_up(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment) * alignment
+}
+
+fn clip_rect(rect: &max_rect::Rect, width: u32, height: u32) -> max_rect::Rect {
+    let x = i32::max(rect.x, 0);
+    let y = i32::max(rect.y, 0);
+    let right = i32::min(rect.x + rect.width, width as i32);
+    let bottom = i32::min(rect.y + rect.height, height as i32);
+    max_rect::Rect { x, y, width: right - x, height: bottom - y }
+}
+
+// Places every size on the first page it fits on, opening pages cloned from the template as needed.
+// Pinned sprites are already on the first page and are skipped.
+fn pack_layout(sizes: &[(u32, u32)], order: &[usize], template: &PageTemplate) -> Layout {
+    // Opening pages was checked when the template was made.
+    let mut layout = Layout { pages: Vec::new(), white_blocks: Vec::new(), placements: vec![None; sizes.len()] };
+    if !template.pins.is_empty() {
+        let (page, white_block) = template.open_page(0).unwrap();
+        layout.pages.push(page);
+        layout.white_blocks.push(white_block);
+        for (index, rect) in template.pins.iter() {
+            layout.placements[*index] = Some((0, rect.clone(), false));
+        }
+    }
+    // A group is packed as a whole when its first sprite comes up, in packing order.
+    let mut group_of = vec![None; sizes.len()];
+    for (group_index, group) in template.groups.iter().enumerate() {
+        for &index in group.iter() {
+            group_of[index] = Some(group_index);
+        }
+    }
+    let mut members = vec![Vec::new(); template.groups.len()];
+    for &index in order {
+        if let (Some(group_index), None) = (group_of[index], &layout.placements[index]) {
+            members[group_index].push(index);
+        }
+    }
+    let mut pinned_groups = vec![false; template.groups.len()];
+    for (index, _) in template.pins.iter() {
+        if let Some(group_index) = group_of[*index] {
+            pinned_groups[group_index] = true;
+        }
+    }
+    for &index in order {
+        if layout.placements[index].is_some() {
+            continue;
+        }
+        if let Some(group_index) = group_of[index] {
+            // A group that doesn't fit on one page is packed sprite by sprite.
+            let members = std::mem::take(&mut members[group_index]);
+            if !members.is_empty() && place_group(sizes, &members, pinned_groups[group_index], template, &mut layout) {
+                continue;
+            }
+        }
+        let mut placement = None;
+        for (page_index, page) in layout.pages.iter_mut().enumerate() {
+            let (rect, rotated) = template.insert_sprite(page, index, sizes[index]);
+            if rect.height > 0 {
+                placement = Some((page_index, rect, rotated));
+                break;
+            }
+        }
+        if placement.is_none() && layout.pages.len() < template.max_pages {
+            let (mut page, white_block) = template.open_page(layout.pages.len()).unwrap();
+            let (rect, rotated) = template.insert_sprite(&mut page, index, sizes[index]);
+            if rect.height > 0 {
+                placement = Some((layout.pages.len(), rect, rotated));
+                layout.pages.push(page);
+                layout.white_blocks.push(white_block);
+            }
+        }
+        layout.placements[index] = placement;
+    }
+    layout
+}
+
+// Places all `members` on the first page that takes them together, which may be a new one.
+fn place_group(sizes: &[(u32, u32)], members: &[usize], pinned: bool, template: &PageTemplate, layout: &mut Layout) -> bool {
+    // A group with a pinned sprite has to join it on the first page.
+    let page_limit = if pinned { 1 } else { usize::min(layout.pages.len() + 1, template.max_pages) };
+    for page_index in 0..page_limit {
+        let (mut page, white_block) = match layout.pages.get(page_index) {
+            Some(page) => (page.clone(), None),
+            None => template.open_page(page_index).unwrap()
+        };
+        let mut placements = Vec::with_capacity(members.len());
+        for &index in members {
+            let (rect, rotated) = template.insert_sprite(&mut page, index, sizes[index]);
+            if rect.height <= 0 {
+                break;
+            }
+            placements.push((index, rect, rotated));
+        }
+        if placements.len() < members.len() {
+            continue;
+        }
+        if page_index < layout.pages.len() {
+            layout.pages[page_index] = page;
+        } else {
+            layout.pages.push(page);
+            layout.white_blocks.push(white_block);
+        }
+        for (index, rect, rotated) in placements {
+            layout.placements[index] = Some((page_index, rect, rotated));
+        }
+        return true;
+    }
+    false
+}
+
+// Repacks the sprites of the last page into the smallest allowed page size that holds them all
+// and returns that size. The page is packed again with the layout's packer, order and flips, and
+// sizes that would cut into a pinned sprite or a reserved region are skipped. The configured size
+// is restored on `cfg` before returning.
+fn shrink_last_page(cfg: &mut SpriteSheetGenConfig, sizes: &[(u32, u32)], order: &[usize], flips: &[bool], margins: &[(u32, u32)], layout: &mut Layout, template: &PageTemplate) -> (u32, u32) {
+    let (width, height) = (cfg.width, cfg.height);
+    let last = match layout.pages.len() {
+        0 => return (width, height),
+        pages => pages - 1
+    };
+    let order: Vec<usize> = order.iter().copied()
+        .filter(|&index| matches!(layout.placements[index], Some((page, ..)) if page == last))
+        .collect();
+    let pins: Vec<(usize, max_rect::Rect)> = if last == 0 { template.pins.clone() } else { vec![] };
+    let (origin, _, _) = cfg.pack_area();
+    let fixed: Vec<max_rect::Rect> = pins.iter().map(|(index, rect)| sprite_rect(rect, origin, margins[*index]))
+        .chain(cfg.reserved_regions.iter().cloned())
+        .collect();
+    let mut page_sizes: Vec<(u32, u32)> = cfg.page_sizes.iter()
+        .map(|&(w, h)| (align_up(w, cfg.alignment), align_up(h, cfg.alignment)))
+        .filter(|&(w, h)| w <= width && h <= height && (w, h) != (width, height))
+        .filter(|&(w, h)| fixed.iter().all(|rect| rect.x + rect.width <= w as i32 && rect.y + rect.height <= h as i32))
+        .collect();
+    page_sizes.sort_by_key(|&(w, h)| (w as u64 * h as u64, w, h));
+    let mut shrunk = None;
+    for (w, h) in page_sizes {
+        cfg.width = w;
+        cfg.height = h;
+        let small_template = match PageTemplate::new(cfg, resize_packer(cfg, &template.prototype), pins.clone(), template.groups.clone(), template.rotations.clone()) {
+            Ok(small_template) => PageTemplate { max_pages: 1, ..small_template },
+            Err(_) => continue
+        };
+        let small = pack_flipped(sizes, &order, flips, &small_template);
+        if order.iter().all(|&index| small.placements[index].is_some()) {
+            shrunk = Some((w, h, small));
+            break;
+        }
+    }
+    cfg.width = width;
+    cfg.height = height;
+    match shrunk {
+        Some((w, h, mut small)) => {
+            for &index in order.iter() {
+                layout.placements[index] = small.placements[index].take().map(|(_, rect, rotated)| (last, rect, rotated));
+            }
+            layout.pages[last] = small.pages.swap_remove(0);
+            layout.white_blocks[last] = small.white_blocks.swap_remove(0);
+            (w, h)
+        }
+        None => (width, height)
+    }
+}
+
+// Lowest atlas row taken by a sprite, a white block or a reserved region.
+fn content_height(cfg: &SpriteSheetGenConfig, layout: &Layout, margins: &[(u32, u32)]) -> u32 {
+    let (origin, _, _) = cfg.pack_area();
+    let sprite_rects = layout.placements.iter().zip(margins.iter()).filter_map(|(placement, &sprite_margins)| {
+        placement.as_ref().map(|(_, rect, _)| sprite_rect(rect, origin, sprite_margins))
+    });
+    let white_rects = layout.white_blocks.iter().flatten().map(|rect| {
+        sprite_rect(rect, origin, cfg.white_block.as_ref().map(|(name, _)| cfg.margins_of(name)).unwrap_or_default())
+    });
+    sprite_rects
+        .chain(white_rects)
+        .chain(cfg.reserved_regions.iter().cloned())
+        .map(|rect| (rect.y + rect.height).max(0) as u32)
+        .max()
+        .unwrap_or(0)
+}
+
+// Binary searches the smallest page height that still takes every sprite `layout` placed, packing
+// with its packer, order and flips, and returns the layout that ends highest. Most heuristics spread
+// sprites over a tall bin, so cropping the first layout alone leaves gaps. `cfg.height` must be a
+// height `layout` fits in and is left unchanged.
+fn shrink_strip(cfg: &mut SpriteSheetGenConfig, sizes: &[(u32, u32)], order: &[usize], flips: &[bool], margins: &[(u32, u32)], layout: Layout, template: &PageTemplate) -> Layout {
+    let tall_height = cfg.height;
+    let (origin, width, _) = cfg.pack_area();
+    let (border_padding, shape_padding) = cfg.border_and_shape_padding();
+    let area: u64 = sizes.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+    let area_bound = (area.div_ceil(u64::max(width as u64, 1)) as u32 + border_padding + origin).saturating_sub(shape_padding);
+    // Pinned sprites and reserved regions have to stay whole.
+    let fixed_bound = template.pins.iter().map(|(index, rect)| sprite_rect(rect, origin, margins[*index]))
+        .chain(cfg.reserved_regions.iter().cloned())
+        .map(|rect| (rect.y + rect.height).max(0) as u32)
+        .max()
+        .unwrap_or(0);
+    let unplaced = layout.unplaced();
+    let mut best_height = content_height(cfg, &layout, margins);
+    let mut best = layout;
+    let (mut low, mut high) = (u32::max(area_bound, fixed_bound), tall_height);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        cfg.height = mid;
+        let probe = PageTemplate::new(cfg, resize_packer(cfg, &template.prototype), template.pins.clone(), template.groups.clone(), template.rotations.clone())
+            .ok()
+            .map(|probe_template| pack_flipped(sizes, order, flips, &PageTemplate { max_pages: 1, ..probe_template }))
+            .filter(|probe| probe.pages.len() <= 1 && probe.unplaced() <= unplaced);
+        match probe {
+            Some(probe) => {
+                let height = content_height(cfg, &probe, margins);
+                if height < best_height {
+                    best_height = height;
+                    best = probe;
+                }
+                high = mid;
+            }
+            None => low = mid + 1
+        }
+    }
+    cfg.height = tall_height;
+    best
+}
+
+fn pack_try_all(cfg: &SpriteSheetGenConfig, sizes: &[(u32, u32)], pins: &[(usize, max_rect::Rect)], groups: &[Vec<usize>], rotations: &[bool]) -> Result<(Layout, PageTemplate, packer::SortOrder), String> {
+    let mut templates = Vec::new();
+    let (_, width, height) = cfg.pack_area();
+    for candidate in packer::Packer::candidates(width, height, cfg.packer_rotation(), cfg.use_waste_map, cfg.guillotine_merge) {
+        // Packers that can't reserve space or align are skipped when the config needs it.
+        if let Ok(template) = PageTemplate::new(cfg, candidate, pins.to_vec(), groups.to_vec(), rotations.to_vec()) {
+            templates.push(template);
+        }
+    }
+    let sort_orders = [packer::SortOrder::None, packer::SortOrder::Area, packer::SortOrder::Perimeter,
+                       packer::SortOrder::MaxSide, packer::SortOrder::Width, packer::SortOrder::Height];
+    let mut best: Option<(Layout, usize, packer::SortOrder)> = None;
+    for &sort_order in sort_orders.iter() {
+        let order = sort_order.sort(sizes);
+        for (template_index, template) in templates.iter().enumerate() {
+            let layout = pack_layout(sizes, &order, template);
+            if best.as_ref().map(|(best_layout, ..)| layout.is_better_than(best_layout)).unwrap_or(true) {
+                best = Some((layout, template_index, sort_order));
+            }
+        }
+    }
+    let (layout, template_index, sort_order) = best.ok_or_else(|| String::from("no packer can keep the reserved regions and alignment"))?;
+    Ok((layout, templates.swap_remove(template_index), sort_order))
+}
+
+// Packs with each flipped size turned 90 degrees up front; the flip is folded into the rotated flags.
+fn pack_flipped(sizes: &[(u32, u32)], order: &[usize], flips: &[bool], template: &PageTemplate) -> Layout {
+    let flipped: Vec<(u32, u32)> = sizes.iter().zip(flips).map(|(&(w, h), &flip)| if flip { (h, w) } else { (w, h) }).collect();
+    let mut layout = pack_layout(&flipped, order, template);
+    for (placement, &flip) in layout.placements.iter_mut().zip(flips) {
+        if let Some((_, _, rotated)) = placement {
+            *rotated ^= flip;
+        }
+    }
+    layout
+}
+
+type BeforeAfter = (LayoutSummary, LayoutSummary);
+
+// Returns the better of `layout` and the optimizer's layout with the order and flips that packed it,
+// and how both layouts scored.
+fn pack_optimized(cfg: &SpriteSheetGenConfig, sizes: &[(u32, u32)], layout: Layout, template: &PageTemplate, order: Vec<usize>) -> (Layout, Vec<usize>, Vec<bool>, Option<BeforeAfter>) {
+    let optimize = match cfg.optimize {
+        Some(optimize) => optimize,
+        None => return (layout, order, vec![false; sizes.len()], None)
+    };
+    let mut flippable: Vec<bool> = sizes.iter().enumerate().map(|(index, &(w, h))| {
+        template.rotations.get(index).copied().unwrap_or(cfg.is_rotation) && w != h
+    }).collect();
+    for (index, _) in template.pins.iter() {
+        flippable[*index] = false;
+    }
+    let (optimized_order, flips) = anneal::anneal(order.clone(), &flippable, &optimize, |order, flips| {
+        pack_flipped(sizes, order, flips, template).cost()
+    });
+    let optimized = pack_flipped(sizes, &optimized_order, &flips, template);
+    let summaries = Some((layout.summary(), optimized.summary()));
+    if optimized.is_better_than(&layout) {
+        (optimized, optimized_order, flips, summaries)
+    } else {
+        (layout, order, vec![false; sizes.len()], summaries)
+    }
+}
+
+// Moves a packed rect into atlas coordinates and strips the sprite's margins from it.
+fn sprite_rect(packed: &max_rect::Rect, origin: u32, (lead, trail): (u32, u32)) -> max_rect::Rect {
+    max_rect::Rect {
+        x: packed.x + (origin + lead) as i32,
+        y: packed.y + (origin + lead) as i32,
+        width: packed.width - (lead + trail) as i32,
+        height: packed.height - (lead + trail) as i32
+    }
+}
+
+// Rotates the sprite as placed and finds its atlas rect; the copy into the page happens in `blit_page`.
+fn process_image(sprite: SpriteImage, insert_rect: max_rect::Rect, rotated: bool, origin: u32, margins: (u32, u32)) -> (RgbaImage, PackedSprite) {
+    let mut rgba_image = sprite.image;
+    if rotated {
+        rgba_image = image::imageops::rotate90(&rgba_image);
+    }
+    let rect = sprite_rect(&insert_rect, origin, margins);
+    (rgba_image, PackedSprite { name: sprite.name, rect, rotated, offset: sprite.offset })
+}
+
+// Blends every sprite into its rect like `imageops::overlay`, one page row per task. Placements
+// never overlap, so the result is the same as overlaying the sprites one after another.
+fn blit_page(out_image: &mut RgbaImage, sprites: &[(RgbaImage, PackedSprite)]) {
+    let row_len = out_image.width() as usize * 4;
+    if row_len == 0 {
+        return;
+    }
+    out_image.par_chunks_mut(row_len).enumerate().for_each(|(y, row)| {
+        let y = y as i32;
+        for (image, packed) in sprites {
+            let rect = &packed.rect;
+            if y < rect.y || y >= rect.y + rect.height {
+                continue;
+            }
+            let src_len = image.width() as usize * 4;
+            let src_start = (y - rect.y) as usize * src_len;
+            let dst_start = rect.x as usize * 4;
+            let src = &image.as_ref()[src_start..src_start + src_len];
+            for (dst, src) in row[dst_start..dst_start + src_len].chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                Rgba::from_slice_mut(dst).blend(Rgba::from_slice(src));
+            }
+        }
+    });
+}
+
+// Returns the sprites with the folder each one was found in, if it was listed with one.
+fn load_sprite_files(paths: &[(PathBuf, Option<String>)]) -> (Vec<SpriteImage>, Vec<Option<String>>) {
+    let loaded: Vec<Vec<SpriteImage>> = paths.par_iter().map(|(path, _)| {
+        let mut sprites = Vec::new();
+        load_sprites(path, &mut sprites);
+        sprites
+    }).collect();
+    let mut folders = Vec::new();
+    for (sprites, (_, folder)) in loaded.iter().zip(paths) {
+        folders.extend(std::iter::repeat_n(folder.clone(), sprites.len()));
+    }
+    (loaded.into_iter().flatten().collect(), folders)
+}
+
+fn folder_name(path: &Path) -> Option<String> {
+    path.file_name().and_then(|os_str| os_str.to_str()).map(String::from)
+}
+
+pub fn sprite_sheet_gen(mut cfg:SpriteSheetGenConfig) -> Result<PackStats,String> {
+    cfg.width = align_up(cfg.width, cfg.alignment);
+    cfg.height = align_up(cfg.height, cfg.alignment);
+    let by_folder = matches!(cfg.grouping, Some(SpriteGrouping::Subfolder));
+    let is_sliced = |path: &Path| cfg.sliced_sheets.iter().any(|(sheet, _)| is_same_file(Path::new(sheet), path));
+    let mut paths:Vec<(PathBuf,Option<String>)> = Vec::new();
     if cfg.sprite_list.is_empty() {
         // 如果 sprite_list 为空，使用原来的逻辑遍历目录
         let read_dir:fs::ReadDir = fs::read_dir(&cfg.dir).map_err(|_| String::from("dir not found"))?;
-        for may_item in read_dir {
-            if let Ok(item) = may_item {
-                let path = item.path();
-                if path.is_dir() {
-                    continue;
+        for item in read_dir.flatten() {
+            let path = item.path();
+            if path.is_dir() {
+                if by_folder {
+                    let folder = folder_name(&path);
+                    for sub_item in fs::read_dir(&path).into_iter().flatten().flatten() {
+                        let sub_path = sub_item.path();
+                        if !sub_path.is_dir() && !is_sliced(&sub_path) {
+                            paths.push((sub_path, folder.clone()));
+                        }
+                    }
                 }
-                process_image(&path, &mut out_image, &mut max_rect, cfg.padding, &mut writed_list);
+                continue;
             }
+            if is_sliced(&path) {
+                continue;
+            }
+            paths.push((path, None));
         }
     } else {
         // 如果 sprite_list 不为空，使用指定的文件列表
-        for sprite_file in &cfg.sprite_list {
-            let path = Path::new(sprite_file);
-            process_image(&path, &mut out_image, &mut max_rect, cfg.padding, &mut writed_list);
+        paths.extend(cfg.sprite_list.iter().map(|file| {
+            let path = PathBuf::from(file);
+            let folder = if by_folder { path.parent().and_then(folder_name) } else { None };
+            (path, folder)
+        }));
+    }
+    let (mut sprites, mut folders) = load_sprite_files(&paths);
+    let sliced: Vec<Vec<SpriteImage>> = cfg.sliced_sheets.par_iter().map(|(sheet, slicer)| {
+        let mut sprites = Vec::new();
+        load_sliced_sheet(Path::new(sheet), slicer, &mut sprites);
+        sprites
+    }).collect();
+    sprites.extend(sliced.into_iter().flatten());
+    folders.resize(sprites.len(), None);
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    if let Some(grouping) = &cfg.grouping {
+        let mut group_indices: HashMap<String, usize> = HashMap::new();
+        for (index, sprite) in sprites.iter().enumerate() {
+            if let Some(name) = grouping.group_name(&sprite.name, folders[index].as_ref()) {
+                let group_index = *group_indices.entry(name.clone()).or_insert_with(|| {
+                    groups.push((name, Vec::new()));
+                    groups.len() - 1
+                });
+                groups[group_index].1.push(index);
+            }
         }
+        groups.retain(|(_, members)| members.len() > 1);
+    }
+    let group_indices: Vec<Vec<usize>> = groups.iter().map(|(_, members)| members.clone()).collect();
+
+    let margins:Vec<(u32,u32)> = sprites.iter().map(|sprite| cfg.margins_of(&sprite.name)).collect();
+    let rotations:Vec<bool> = sprites.iter().map(|sprite| cfg.rotation_of(&sprite.name)).collect();
+    let sizes:Vec<(u32,u32)> = sprites.iter().zip(margins.iter()).map(|(sprite, &(lead, trail))| {
+        let (w, h) = sprite.image.dimensions();
+        (w + lead + trail, h + lead + trail)
+    }).collect();
+    if cfg.strip.is_some() {
+        // Tall enough for every sprite stacked below anything pinned or reserved; the page is cut
+        // down to the used height after packing.
+        let (border_padding, shape_padding) = cfg.border_and_shape_padding();
+        let stacked: u32 = sizes.iter().map(|&(w, h)| u32::max(w, h) + cfg.alignment).sum();
+        let white_block = cfg.white_block.as_ref().map(|(_, size)| size + shape_padding * 2 + cfg.alignment).unwrap_or(0);
+        let fixed = cfg.reserved_regions.iter().map(|rect| (rect.y + rect.height).max(0) as u32)
+            .chain(cfg.pinned_sprites.iter().map(|(_, _, y)| *y))
+            .max()
+            .unwrap_or(0);
+        // Saturating so a pin far off the page is reported by the pin check below rather than overflowing.
+        cfg.height = fixed.saturating_add(stacked + white_block + (border_padding + shape_padding + cfg.alignment) * 2);
     }
-    let def_name = Path::new(&cfg.dir).file_name().and_then(|os_str| os_str.to_str()).map(|s| String::from(s));
+    let (origin, _, _) = cfg.pack_area();
+    let mut pins = Vec::new();
+    for (name, x, y) in cfg.pinned_sprites.iter() {
+        match sprites.iter().position(|sprite| &sprite.name == name) {
+            Some(index) => {
+                let (w, h) = sprites[index].image.dimensions();
+                let fits = |at: u32, len: u32, limit: u32| at.checked_add(len).is_some_and(|end| end <= limit);
+                if !fits(*x, w, cfg.width) || !fits(*y, h, cfg.height) {
+                    return Err(format!("pinned sprite {} doesn't fit on the page at {},{}", name, x, y));
+                }
+                let lead = (origin + margins[index].0) as i32;
+                let rect = max_rect::Rect { x: *x as i32 - lead, y: *y as i32 - lead, width: sizes[index].0 as i32, height: sizes[index].1 as i32 };
+                pins.push((index, rect));
+            }
+            None => eprintln!("can't pin {}, no such sprite", name)
+        }
+    }
+    let (layout, template, sort_order) = if cfg.try_all {
+        pack_try_all(&cfg, &sizes, &pins, &group_indices, &rotations)?
+    } else {
+        let template = PageTemplate::new(&cfg, create_packer(&cfg), pins, group_indices, rotations)?;
+        (pack_layout(&sizes, &cfg.sort_order.sort(&sizes), &template), template, cfg.sort_order)
+    };
+    let best_packer = if cfg.try_all { Some((template.prototype.to_string(), sort_order)) } else { None };
+    let (mut layout, order, flips, optimized) = pack_optimized(&cfg, &sizes, layout, &template, sort_order.sort(&sizes));
+    for (name, members) in groups.iter() {
+        let mut pages = members.iter().filter_map(|&index| layout.placements[index].as_ref().map(|(page, ..)| *page));
+        if let Some(first) = pages.next() {
+            if pages.any(|page| page != first) {
+                eprintln!("group {} doesn't fit on one page and was split", name);
+            }
+        }
+    }
+    if let Some(rounding) = cfg.strip {
+        layout = shrink_strip(&mut cfg, &sizes, &order, &flips, &margins, layout, &template);
+        let (border_padding, _) = cfg.border_and_shape_padding();
+        let height = content_height(&cfg, &layout, &margins) + border_padding;
+        cfg.height = align_up(rounding.round(u32::max(height, 1)), cfg.alignment);
+    }
+    let page_count = usize::max(layout.pages.len(), 1);
+    let mut page_sizes = vec![(cfg.width, cfg.height); page_count];
+    if !cfg.page_sizes.is_empty() && cfg.strip.is_none() {
+        page_sizes[page_count - 1] = shrink_last_page(&mut cfg, &sizes, &order, &flips, &margins, &mut layout, &template);
+    }
+    let processed: Vec<Result<(usize, RgbaImage, PackedSprite), String>> = sprites
+        .into_par_iter()
+        .zip(layout.placements.into_par_iter())
+        .zip(margins.into_par_iter())
+        .map(|((sprite, placement), sprite_margins)| match placement {
+            Some((page, rect, rotated)) => {
+                let (image, packed) = process_image(sprite, rect, rotated, origin, sprite_margins);
+                Ok((page, image, packed))
+            }
+            None => Err(sprite.name)
+        })
+        .collect();
+    let mut page_sprites:Vec<Vec<(RgbaImage,PackedSprite)>> = (0..page_count).map(|_| Vec::new()).collect();
+    let mut stats = PackStats { pages: page_count, best_packer, optimized, ..PackStats::default() };
+    for item in processed {
+        match item {
+            Ok((page, image, packed)) => page_sprites[page].push((image, packed)),
+            Err(name) => {
+                eprintln!("image to small, can't place {}", name);
+                stats.sprites_unplaced += 1;
+            }
+        }
+    }
+    let mut out_images:Vec<RgbaImage> = page_sizes.iter().map(|&(w, h)| image::ImageBuffer::new(w, h)).collect();
+    out_images.par_iter_mut().zip(page_sprites.par_iter()).for_each(|(out_image, sprites)| blit_page(out_image, sprites));
+    let mut writed_lists:Vec<Vec<PackedSprite>> = page_sprites
+        .into_iter()
+        .map(|sprites| sprites.into_iter().map(|(_, packed)| packed).collect())
+        .collect();
+
+    for rect in writed_lists.iter().flatten().map(|packed| &packed.rect) {
+        stats.sprites_packed += 1;
+        stats.used_pixels += rect.width as u64 * rect.height as u64;
+    }
+    if let Some((name, _)) = &cfg.white_block {
+        for (page, white_block) in layout.white_blocks.iter().enumerate() {
+            if let Some(rect) = white_block {
+                let rect = sprite_rect(rect, origin, cfg.margins_of(name));
+                for y in rect.y..rect.y + rect.height {
+                    for x in rect.x..rect.x + rect.width {
+                        out_images[page].put_pixel(x as u32, y as u32, image::Rgba([255, 255, 255, 255]));
+                    }
+                }
+                stats.used_pixels += rect.width as u64 * rect.height as u64;
+                writed_lists[page].push(PackedSprite { name: name.clone(), rect, rotated: false, offset: None });
+            }
+        }
+    }
+    let page_pixels:u64 = page_sizes.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+    stats.wasted_pixels = page_pixels - stats.used_pixels;
+    if page_pixels > 0 {
+        stats.occupancy = stats.used_pixels as f32 / page_pixels as f32;
+    }
+
+    let def_name = Path::new(&cfg.dir).file_name().and_then(|os_str| os_str.to_str()).map(String::from);
     let out_path = cfg.out_file.clone().unwrap_or(def_name.unwrap_or(String::from("default")));
-    out_image.save(out_path.clone() + ".png").map_err(|_| String::from("save image error"))?;
-    (cfg.write_desc_fn)(&out_path,&cfg,&writed_list);
-    Ok(true)
+    for (page, (out_image, writed_list)) in out_images.iter().zip(writed_lists.iter()).enumerate() {
+        let page_path = if cfg.max_pages > 1 { format!("{}_{}", out_path, page) } else { out_path.clone() };
+        out_image.save(page_path.clone() + ".png").map_err(|_| String::from("save image error"))?;
+        // The descriptor reads the page size from the config.
+        cfg.width = page_sizes[page].0;
+        cfg.height = page_sizes[page].1;
+        (cfg.write_desc_fn)(&page_path,&cfg,writed_list);
+    }
+    Ok(stats)
 }
 
-fn write_default_json(out_path:&String,cfg:&SpriteSheetGenConfig,data_list:&Vec<(String,max_rect::Rect)>) {
+fn write_default_json(out_path:&String,cfg:&SpriteSheetGenConfig,data_list:&Vec<PackedSprite>) {
     let mut meta_map:Map<String,Value> = Map::default();
-    let tex_name = Path::new(out_path).file_stem().and_then(|os_str| os_str.to_str()).map(|s| String::from(s));
+    let tex_name = Path::new(out_path).file_stem().and_then(|os_str| os_str.to_str()).map(String::from);
     meta_map.insert(String::from("texture"), Value::String(tex_name.unwrap() + ".png"));
     meta_map.insert(String::from("width"), Value::Number(serde_json::Number::from(cfg.width)));
     meta_map.insert(String::from("height"), Value::Number(serde_json::Number::from(cfg.height)));
+    if let Some((name, _)) = &cfg.white_block {
+        if let Some(white) = data_list.iter().find(|item| &item.name == name) {
+            let u = (white.rect.x as f64 + white.rect.width as f64 / 2.0) / cfg.width as f64;
+            let v = (white.rect.y as f64 + white.rect.height as f64 / 2.0) / cfg.height as f64;
+            let uv = [u, v].iter().filter_map(|&f| Number::from_f64(f)).map(Value::Number).collect();
+            meta_map.insert(String::from("white_uv"), Value::Array(uv));
+        }
+    }
     let mut sprite_list:Vec<Value>  = Vec::new();
     for item in data_list {
         let mut sprite_map = Map::default();
-        sprite_map.insert(String::from("name"),Value::String(String::from(item.0.clone())));
-        sprite_map.insert(String::from("x"),Value::Number(Number::from(item.1.x)));
-        sprite_map.insert(String::from("y"),Value::Number(Number::from(item.1.y)));
-        sprite_map.insert(String::from("width"),Value::Number(Number::from(item.1.width)));
-        sprite_map.insert(String::from("height"),Value::Number(Number::from(item.1.height)));
+        sprite_map.insert(String::from("name"),Value::String(item.name.clone()));
+        sprite_map.insert(String::from("x"),Value::Number(Number::from(item.rect.x)));
+        sprite_map.insert(String::from("y"),Value::Number(Number::from(item.rect.y)));
+        sprite_map.insert(String::from("width"),Value::Number(Number::from(item.rect.width)));
+        sprite_map.insert(String::from("height"),Value::Number(Number::from(item.rect.height)));
+        sprite_map.insert(String::from("rotated"),Value::Bool(item.rotated));
+        if let Some((offset_x,offset_y)) = item.offset {
+            sprite_map.insert(String::from("offset_x"),Value::Number(Number::from(offset_x)));
+            sprite_map.insert(String::from("offset_y"),Value::Number(Number::from(offset_y)));
+        }
         sprite_list.push(Value::Object(sprite_map));
     }
     let mut out_json_map:Map<String,Value> = Map::default();
@@ -166,38 +1218,336 @@ fn write_default_json(out_path:&String,cfg:&SpriteSheetGenConfig,data_list:&Vec<
 #[cfg(test)]
 mod tests {
     use crate::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack};
+    use crate::packer::{Packer,SortOrder};
+    use crate::{pack_layout,PageTemplate};
+
+    #[test]
+    fn test_pack_pages() {
+        // Only one 40x40 sprite fits on a 64x64 page, so three sprites need three pages.
+        let sizes = [(10,10),(40,40),(40,40),(40,40)];
+        let order = SortOrder::Area.sort(&sizes);
+        assert_eq!(order,vec![1,2,3,0]);
+        let prototype = Packer::MaxRects(MaxRectsBinPack::new(64,64,false),FreeRectChoiceHeuristic::BestAreaFit);
+        let layout = pack_layout(&sizes,&order,&PageTemplate {prototype,pins:vec![],white_block:None,groups:vec![],rotations:vec![],max_pages:2});
+        assert_eq!(layout.pages.len(),2);
+        assert_eq!(layout.unplaced(),1);
+        assert!(layout.placements[3].is_none());
+        assert_eq!(layout.placements[0].as_ref().map(|(page,..)| *page),Some(0));
+    }
    
     #[test]
     fn test_insert() {
+        use crate::max_rect::Rect;
         let mut max_rect = MaxRectsBinPack::new(1024,1024, false);
         let test_data =  [(100,100),(32,32),(32,12),(50,50),(27,15),(128,45),(1000,198),(44,89)];
+        let mut placed:Vec<Rect> = Vec::new();
         for tp in test_data.iter() {
-            max_rect.insert(tp.0, tp.1, FreeRectChoiceHeuristic::BestAreaFit);
-        }
-        dbg!(&max_rect);
-        draw_debug_rect(&max_rect)
-    }
-
-    fn draw_debug_rect(max_rect:&MaxRectsBinPack) {
-        use image::DynamicImage;
-        use image::{Rgba};
-        use  image::{GenericImage};
-        use imageproc::drawing;
-        use imageproc::rect::{Rect};
-        let mut new_image = DynamicImage::new_rgba8(max_rect.width(),max_rect.height());
-        let used_list = max_rect.used_rect();
-        for use_rect in used_list {
-          let color = Rgba([255,0,0,255]);
-           drawing::draw_hollow_rect_mut(&mut new_image,Rect::at(use_rect.x, use_rect.y)
-                                                     .of_size(use_rect.width as u32, use_rect.height as u32), color);
-        }
-
-        for free_rect in max_rect.free_rect() {
-            let color = Rgba([105,0,255,255]);
-             drawing::draw_filled_rect_mut(&mut new_image,Rect::at(free_rect.x, free_rect.y)
-                                                       .of_size(free_rect.width as u32, free_rect.height as u32), color);
-          }
-        new_image.put_pixel(0, 0, image::Rgba([1,1,1,1]));
-        new_image.save("test2.png").unwrap();
+            let (rect,rotated) = max_rect.insert(tp.0, tp.1, FreeRectChoiceHeuristic::BestAreaFit);
+            assert!(!rotated);
+            assert_eq!((rect.width,rect.height),*tp);
+            assert!(rect.x >= 0 && rect.y >= 0 && rect.x + rect.width <= 1024 && rect.y + rect.height <= 1024);
+            for other in placed.iter() {
+                assert!(rect.x >= other.x + other.width || other.x >= rect.x + rect.width ||
+                        rect.y >= other.y + other.height || other.y >= rect.y + rect.height);
+            }
+            placed.push(rect);
+        }
+        assert_eq!(max_rect.used_rect().len(),test_data.len());
+    }
+
+    #[test]
+    fn test_groups_share_a_page() {
+        // 1 and 3 don't fit next to 0, so their group opens a new page, taking 4 along
+        // although 4 alone would still fit on the first page.
+        let prototype = Packer::MaxRects(MaxRectsBinPack::new(64,64,false),FreeRectChoiceHeuristic::BestAreaFit);
+        let sizes = [(64,40),(30,30),(64,10),(30,30),(10,10)];
+        let template = PageTemplate {prototype,pins:vec![],white_block:None,groups:vec![vec![1,3,4]],rotations:vec![],max_pages:3};
+        let layout = pack_layout(&sizes,&[0,1,2,3,4],&template);
+        let pages:Vec<usize> = layout.placements.iter().map(|placement| placement.as_ref().unwrap().0).collect();
+        assert_eq!(pages,vec![0,1,0,1,1]);
+    }
+
+    #[test]
+    fn test_upright_sprites() {
+        // Only the rotatable 40x10 sprite may stand up in the 20 wide page.
+        let prototype = Packer::MaxRects(MaxRectsBinPack::new(20,100,true),FreeRectChoiceHeuristic::BestAreaFit);
+        let template = PageTemplate {prototype,pins:vec![],white_block:None,groups:vec![],rotations:vec![true,false],max_pages:1};
+        let layout = pack_layout(&[(40,10),(40,10)],&[0,1],&template);
+        assert!(layout.placements[0].as_ref().unwrap().2);
+        assert!(layout.placements[1].is_none());
+    }
+
+    #[test]
+    fn test_white_block() {
+        use crate::max_rect::Rect;
+        let prototype = Packer::MaxRects(MaxRectsBinPack::new(64,64,false),FreeRectChoiceHeuristic::BestAreaFit);
+        let template = PageTemplate {prototype,pins:vec![],white_block:Some(6),groups:vec![],rotations:vec![],max_pages:4};
+        let sizes = [(40,40),(40,40),(62,62)];
+        let layout = pack_layout(&sizes,&[0,1,2],&template);
+        assert_eq!(layout.white_blocks.len(),2);
+        assert!(layout.placements[2].is_none());
+        let overlaps = |a:&Rect,b:&Rect| a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height;
+        for (page,rect,_) in layout.placements.iter().flatten() {
+            let block = layout.white_blocks[*page].as_ref().unwrap();
+            assert_eq!((block.width,block.height),(6,6));
+            assert!(!overlaps(block,rect));
+        }
+    }
+
+    #[test]
+    fn test_pinned_sprites() {
+        use crate::max_rect::Rect;
+        let mut prototype = Packer::MaxRects(MaxRectsBinPack::new(64,64,false),FreeRectChoiceHeuristic::BottomLeftRule);
+        assert!(prototype.reserve(Rect {x:0,y:0,width:8,height:8}));
+        let pins = vec![(1,Rect {x:32,y:32,width:32,height:32})];
+        let template = PageTemplate {prototype,pins,white_block:None,groups:vec![],rotations:vec![],max_pages:2};
+        let sizes = [(16,16),(32,32),(56,8)];
+        let layout = pack_layout(&sizes,&[0,1,2],&template);
+        let (page,rect,rotated) = layout.placements[1].clone().unwrap();
+        assert_eq!((page,rect.x,rect.y,rotated),(0,32,32,false));
+        let (_,rect,_) = layout.placements[0].clone().unwrap();
+        assert_eq!((rect.x,rect.y),(8,0));
+        let (_,rect,_) = layout.placements[2].clone().unwrap();
+        assert_eq!((rect.x,rect.y),(0,16));
+    }
+
+    #[test]
+    fn test_pin_off_page() {
+        use crate::{sprite_sheet_gen,HeightRounding,SpriteSheetGenConfig};
+        use image::RgbaImage;
+        let dir = std::env::temp_dir().join(format!("spritesheet-gen-pin-{}",std::process::id()));
+        let res = dir.join("res");
+        std::fs::create_dir_all(&res).unwrap();
+        RgbaImage::new(8,8).save(res.join("a.png")).unwrap();
+        // Coordinates whose end would wrap around u32 must be rejected, not wrap back onto the page.
+        for &(x,y,strip) in [(u32::MAX - 5,0,false),(0,u32::MAX - 5,false),(0,u32::MAX - 5,true)].iter() {
+            let mut cfg = SpriteSheetGenConfig::default();
+            cfg.set_dir(res.to_str().unwrap());
+            cfg.set_out_file(dir.join("out").to_str().unwrap());
+            cfg.set_size(64,64);
+            cfg.pin_sprite("a",x,y);
+            if strip {
+                cfg.set_strip_packing(HeightRounding::Tight);
+            }
+            let err = sprite_sheet_gen(cfg).err().unwrap();
+            assert_eq!(err,format!("pinned sprite a doesn't fit on the page at {},{}",x,y));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pack_area() {
+        use crate::SpriteSheetGenConfig;
+        let mut cfg = SpriteSheetGenConfig::default();
+        cfg.set_size(64,32);
+        cfg.set_padding(2);
+        // Padding every sprite on all sides: 2 at the edges and 4 between sprites.
+        assert_eq!(cfg.pack_area(),(2,64,32));
+        assert_eq!(cfg.margins_of("a"),(0,4));
+        cfg.set_border_padding(0);
+        cfg.set_shape_padding(8);
+        assert_eq!(cfg.pack_area(),(0,72,40));
+        assert_eq!(cfg.margins_of("a"),(0,8));
+        cfg.set_alignment(4);
+        cfg.set_border_padding(1);
+        assert_eq!(cfg.pack_area(),(4,67,35));
+    }
+
+    #[test]
+    fn test_aligned_padding_override() {
+        use crate::{create_packer,sprite_rect,SpriteOverride,SpriteSheetGenConfig};
+        let mut cfg = SpriteSheetGenConfig::default();
+        cfg.set_size(64,64);
+        cfg.set_padding(1);
+        cfg.set_alignment(4);
+        cfg.set_sprite_override("a",SpriteOverride {rotation:None,padding:Some(3)});
+        assert_eq!(cfg.margins_of("a"),(4,3));
+        assert_eq!(cfg.margins_of("b"),(0,2));
+        let names = ["b","a","b","a"];
+        let margins:Vec<(u32,u32)> = names.iter().map(|name| cfg.margins_of(name)).collect();
+        let sizes:Vec<(u32,u32)> = margins.iter().map(|&(lead,trail)| (5 + lead + trail,7 + lead + trail)).collect();
+        let template = PageTemplate::new(&cfg,create_packer(&cfg),vec![],vec![],vec![]).unwrap();
+        let layout = pack_layout(&sizes,&[0,1,2,3],&template);
+        let (origin,_,_) = cfg.pack_area();
+        for (placement,&sprite_margins) in layout.placements.iter().zip(margins.iter()) {
+            let rect = sprite_rect(&placement.as_ref().unwrap().1,origin,sprite_margins);
+            assert_eq!((rect.x % 4,rect.y % 4,rect.width,rect.height),(0,0,5,7));
+        }
+    }
+
+    #[test]
+    fn test_reserved_region_gap() {
+        use crate::{create_packer,sprite_rect,SpriteSheetGenConfig};
+        use crate::max_rect::Rect;
+        let mut cfg = SpriteSheetGenConfig::default();
+        cfg.set_size(64,64);
+        cfg.set_border_padding(0);
+        cfg.set_shape_padding(4);
+        cfg.add_reserved_region(Rect {x:0,y:0,width:16,height:64});
+        let template = PageTemplate::new(&cfg,create_packer(&cfg),vec![],vec![],vec![]).unwrap();
+        let margins = cfg.margins_of("a");
+        let layout = pack_layout(&[(20 + margins.1,20 + margins.1)],&[0],&template);
+        let rect = sprite_rect(&layout.placements[0].as_ref().unwrap().1,0,margins);
+        assert_eq!((rect.x,rect.width),(20,20));
+    }
+
+    #[test]
+    fn test_shrink_last_page() {
+        use crate::{create_packer,shrink_last_page,SpriteSheetGenConfig};
+        let mut cfg = SpriteSheetGenConfig::default();
+        cfg.set_size(64,64);
+        cfg.set_max_pages(2);
+        cfg.set_page_sizes(vec![(32,32),(16,16),(128,128)]);
+        let template = PageTemplate::new(&cfg,create_packer(&cfg),vec![],vec![],vec![]).unwrap();
+        // The first sprite fills a page, so the other two end up alone on the second one.
+        let sizes = [(64,64),(20,10),(10,20)];
+        let mut layout = pack_layout(&sizes,&[0,1,2],&template);
+        assert_eq!(layout.pages.len(),2);
+        assert_eq!(shrink_last_page(&mut cfg,&sizes,&[0,1,2],&[false; 3],&[(0,0); 3],&mut layout,&template),(32,32));
+        assert_eq!((cfg.width,cfg.height),(64,64));
+        assert_eq!(layout.pages[1].width(),32);
+        for placement in layout.placements[1..].iter() {
+            let (page,rect,_) = placement.clone().unwrap();
+            assert_eq!(page,1);
+            assert!(rect.x + rect.width <= 32 && rect.y + rect.height <= 32);
+        }
+    }
+
+    #[test]
+    fn test_shrink_keeps_pins_and_regions() {
+        use crate::{create_packer,shrink_last_page,SpriteSheetGenConfig};
+        use crate::max_rect::Rect;
+        use crate::skyline::{SkylineBinPack,SkylineHeuristic};
+        let mut cfg = SpriteSheetGenConfig::default();
+        cfg.set_size(64,64);
+        cfg.set_padding(0);
+        cfg.set_page_sizes(vec![(32,32)]);
+        // The pinned sprite only partly overlaps the 32x32 page.
+        let pins = vec![(0,Rect {x:20,y:20,width:20,height:20})];
+        let template = PageTemplate::new(&cfg,create_packer(&cfg),pins,vec![],vec![]).unwrap();
+        let sizes = [(20,20),(8,8)];
+        let mut layout = pack_layout(&sizes,&[0,1],&template);
+        assert_eq!(shrink_last_page(&mut cfg,&sizes,&[0,1],&[false; 2],&[(0,0); 2],&mut layout,&template),(64,64));
+        assert_eq!(layout.pages[0].width(),64);
+
+        // Without the pin the page shrinks with the packer it was packed with, unless a reserved
+        // region reaches past the smaller size.
+        let prototype = Packer::Skyline(SkylineBinPack::new(64,64,false,false),SkylineHeuristic::BottomLeft);
+        let template = PageTemplate::new(&cfg,prototype.clone(),vec![],vec![],vec![]).unwrap();
+        let mut layout = pack_layout(&sizes,&[0,1],&template);
+        assert_eq!(shrink_last_page(&mut cfg,&sizes,&[0,1],&[false; 2],&[(0,0); 2],&mut layout,&template),(32,32));
+        assert!(matches!(layout.pages[0],Packer::Skyline(_,SkylineHeuristic::BottomLeft)));
+        cfg.add_reserved_region(Rect {x:40,y:0,width:8,height:8});
+        let template = PageTemplate::new(&cfg,create_packer(&cfg),vec![],vec![],vec![]).unwrap();
+        let mut layout = pack_layout(&sizes,&[0,1],&template);
+        assert_eq!(shrink_last_page(&mut cfg,&sizes,&[0,1],&[false; 2],&[(0,0); 2],&mut layout,&template),(64,64));
+    }
+
+    #[test]
+    fn test_height_rounding() {
+        use crate::HeightRounding;
+        assert_eq!(HeightRounding::Tight.round(301),301);
+        assert_eq!(HeightRounding::PowerOfTwo.round(301),512);
+        assert_eq!(HeightRounding::PowerOfTwo.round(256),256);
+        assert_eq!(HeightRounding::Multiple(16).round(301),304);
+    }
+
+    #[test]
+    fn test_strip_height() {
+        use crate::{sprite_sheet_gen,HeightRounding,SpriteSheetGenConfig};
+        use image::RgbaImage;
+        let dir = std::env::temp_dir().join(format!("spritesheet-gen-strip-{}",std::process::id()));
+        let res = dir.join("res");
+        std::fs::create_dir_all(&res).unwrap();
+        // A 20 wide sprite can't share a row with the 30 wide ones, so the strip is 10 + 10 + 13 high.
+        for (name,w,h) in [("a",30,10),("b",30,10),("c",20,13)].iter() {
+            RgbaImage::new(*w,*h).save(res.join(format!("{}.png",name))).unwrap();
+        }
+        for (rounding,height) in [(HeightRounding::Tight,33),(HeightRounding::PowerOfTwo,64),(HeightRounding::Multiple(8),40)].iter() {
+            let out = dir.join("strip");
+            let mut cfg = SpriteSheetGenConfig::default();
+            cfg.set_dir(res.to_str().unwrap());
+            cfg.set_out_file(out.to_str().unwrap());
+            cfg.set_size(32,16);
+            cfg.set_padding(0);
+            cfg.set_is_rotation(false);
+            cfg.set_strip_packing(*rounding);
+            let stats = sprite_sheet_gen(cfg).unwrap();
+            assert_eq!((stats.pages,stats.sprites_packed),(1,3));
+            assert_eq!(image::image_dimensions(out.with_extension("png")).unwrap(),(32,*height));
+            let json:serde_json::Value = serde_json::from_str(&std::fs::read_to_string(out.with_extension("json")).unwrap()).unwrap();
+            assert_eq!(json["meta"]["height"],*height);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_strip_height_search() {
+        use crate::{sprite_sheet_gen,HeightRounding,SpriteSheetGenConfig};
+        use image::RgbaImage;
+        let dir = std::env::temp_dir().join(format!("spritesheet-gen-strip-search-{}",std::process::id()));
+        let res = dir.join("res");
+        std::fs::create_dir_all(&res).unwrap();
+        for (name,w,h) in [("a",16,8),("b",16,8),("c",16,8),("d",16,8),("e",8,20),("f",24,6)].iter() {
+            RgbaImage::new(*w,*h).save(res.join(format!("{}.png",name))).unwrap();
+        }
+        // Contact point scores the bottom of a tall bin like any other edge, so only a height
+        // search gets it as low as bottom-left.
+        for heuristic in [FreeRectChoiceHeuristic::BottomLeftRule,FreeRectChoiceHeuristic::ContactPointRule].iter() {
+            let out = dir.join("strip");
+            let mut cfg = SpriteSheetGenConfig::default();
+            cfg.set_dir(res.to_str().unwrap());
+            cfg.set_out_file(out.to_str().unwrap());
+            cfg.set_size(32,16);
+            cfg.set_padding(0);
+            cfg.set_is_rotation(false);
+            cfg.set_heuristic(*heuristic);
+            cfg.set_strip_packing(HeightRounding::Tight);
+            assert_eq!(sprite_sheet_gen(cfg).unwrap().sprites_packed,6);
+            assert_eq!(image::image_dimensions(out.with_extension("png")).unwrap(),(32,34));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_blit_page() {
+        use crate::{blit_page,process_image,SpriteImage};
+        use image::{Rgba,RgbaImage};
+        let sizes = [(7,3),(5,9),(12,4),(3,3),(6,6)];
+        let prototype = Packer::MaxRects(MaxRectsBinPack::new(32,32,true),FreeRectChoiceHeuristic::BottomLeftRule);
+        let layout = pack_layout(&sizes,&SortOrder::Area.sort(&sizes),&PageTemplate {prototype,pins:vec![],white_block:None,groups:vec![],rotations:vec![],max_pages:1});
+        let mut sprites = Vec::new();
+        for (i,(&(w,h),placement)) in sizes.iter().zip(layout.placements).enumerate() {
+            // Fully clear, half and fully opaque pixels, which a plain copy gets wrong.
+            let image = RgbaImage::from_fn(w - 2,h - 2,|x,y| Rgba([(i * 40 + 7) as u8,(x * 30) as u8,(y * 20) as u8,((x + y) * 85 + i as u32) as u8]));
+            let (_,rect,rotated) = placement.unwrap();
+            sprites.push(process_image(SpriteImage {name:i.to_string(),image,offset:None},rect,rotated,0,(1,1)));
+        }
+        let mut serial = RgbaImage::new(32,32);
+        let mut copied = RgbaImage::new(32,32);
+        for (image,packed) in sprites.iter() {
+            image::imageops::overlay(&mut serial,image,packed.rect.x as u32,packed.rect.y as u32);
+            image::imageops::replace(&mut copied,image,packed.rect.x as u32,packed.rect.y as u32);
+        }
+        let mut parallel = RgbaImage::new(32,32);
+        blit_page(&mut parallel,&sprites);
+        assert_ne!(serial.as_ref(),copied.as_ref());
+        assert_eq!(parallel.into_raw(),serial.into_raw());
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let mut max_rect = MaxRectsBinPack::new(100,100,false);
+        assert_eq!(max_rect.fragmentation(),0.0);
+        max_rect.insert(50,100,FreeRectChoiceHeuristic::BestAreaFit);
+        max_rect.insert(50,50,FreeRectChoiceHeuristic::BestAreaFit);
+        assert_eq!(max_rect.used_area(),7500);
+        assert_eq!(max_rect.free_area(),2500);
+        assert_eq!(max_rect.occupancy(),0.75);
+        let largest = max_rect.largest_free_rect().unwrap();
+        assert_eq!((largest.width,largest.height),(50,50));
+        assert_eq!(max_rect.fragmentation(),0.0);
+        max_rect.insert(10,10,FreeRectChoiceHeuristic::BottomLeftRule);
+        assert!(max_rect.fragmentation() > 0.0);
     }
 }
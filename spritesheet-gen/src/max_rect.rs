@@ -1,370 +1,596 @@
-#[derive(Debug,Clone)]
-pub struct Rect {
-   pub x:i32,
-   pub y:i32,
-   pub width:i32,
-   pub height:i32
-}
-
-impl Default for Rect {
-    fn default() -> Rect {
-        Rect {x:0,y:0,width:0,height:0}
-    }
-}
-
-pub enum FreeRectChoiceHeuristic {
-    BestShortSideFit,
-    BestLongSideFit,
-    BestAreaFit,
-    BottomLeftRule,
-    ContactPointRule
-}
-
-#[derive(Debug)]
-pub struct MaxRectsBinPack {
-    width:u32,
-    height:u32,
-    allow_rotations:bool,
-    used_rect:Vec<Rect>,
-    free_rect:Vec<Rect>
-}
-
-impl Default for MaxRectsBinPack {
-    fn default() -> Self {
-        MaxRectsBinPack {
-            width:0,
-            height:0,
-            allow_rotations:true,
-            used_rect:Vec::new(),
-            free_rect:Vec::new()
-        }
-    }
-}
-
-impl MaxRectsBinPack {
-    pub fn new(width:u32,height:u32,rotations:bool) -> Self {
-        let mut max_rect = MaxRectsBinPack::default();
-        max_rect.init(width, height, rotations);
-        max_rect   
-    }
-
-    pub fn width(&self) -> u32 {
-        self.width
-    }
-
-    pub fn height(&self) -> u32 {
-        self.height
-    }
-
-    pub fn free_rect(&self) -> &Vec<Rect> {
-        &self.free_rect
-    }
-
-    pub fn used_rect(&self) -> &Vec<Rect> {
-        &self.used_rect
-    }
-
-    pub fn init(&mut self,width:u32,height:u32,rotations:bool) {
-        self.width = width;
-        self.height = height;
-        self.allow_rotations = rotations;
-        self.free_rect.clear();
-        self.used_rect.clear();
-
-        self.free_rect.push(Rect {x:0,y:0,width:width as i32,height:height as i32});
-    }
-
-    pub fn insert(&mut self,width:i32,height:i32,method:FreeRectChoiceHeuristic) -> Rect {
-        let mut score1 = 0;
-        let mut score2 = 0;
-        let new_node = match method {
-            FreeRectChoiceHeuristic::BestShortSideFit => {
-                self.find_best_short_side_fit(width, height,&mut score1,&mut score2)
-            },
-            FreeRectChoiceHeuristic::BottomLeftRule => {
-                self.find_bottom_left(width, height,&mut score1,&mut score2)
-            },
-            FreeRectChoiceHeuristic::ContactPointRule => {
-                self.find_contact_point(width, height,&mut score1)
-            },
-            FreeRectChoiceHeuristic::BestAreaFit => {
-                self.find_best_area_fit(width,height,&mut score1,&mut score2)
-            },
-            FreeRectChoiceHeuristic::BestLongSideFit => {
-                self.find_best_long_side_fit(width, height,&mut score1,&mut score2)
-            }
-        };
-        if new_node.height == 0 {
-            return new_node;
-        }
-        let mut num_rect_to_process = self.free_rect.len();
-        let mut i = 0;
-        while i < num_rect_to_process {
-            let free_rect:Rect = unsafe { self.free_rect.get_unchecked(i).clone() };
-            if self.split_free_node(free_rect,&new_node) {
-                self.free_rect.remove(i);
-                num_rect_to_process -= 1;
-            } else {
-                i += 1;
-            }
-        }
-        self.prune_free_list();
-        self.used_rect.push(new_node.clone());
-        new_node
-    }
-
-    fn find_best_short_side_fit(&mut self,width:i32,height:i32,best_short_side_fit:&mut i32,best_long_side_fit:&mut i32) -> Rect{
-        let mut best_node = Rect::default();
-        *best_short_side_fit = i32::max_value();
-        for rect in self.free_rect.iter() {
-            if rect.width >= width && rect.height >= height {
-                let left_over_horiz = i32::abs(rect.width - width);
-                let left_over_vert = i32::abs(rect.height - height);
-                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
-                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
-                if short_side_fit < *best_short_side_fit || (short_side_fit == *best_short_side_fit && long_side_fit < *best_long_side_fit) {
-                    best_node.x = rect.x;
-                    best_node.y = rect.y;
-                    best_node.width = width;
-                    best_node.height = height;
-                    *best_short_side_fit = short_side_fit;
-                    *best_long_side_fit = long_side_fit; 
-                }
-            } else if self.allow_rotations && rect.width >= height && rect.height >= width {
-                let flip_left_over_horiz = i32::abs(rect.width - height);
-                let flip_left_over_vert = i32::abs(rect.height - width);
-                let flip_short_side_fit = i32::min(flip_left_over_horiz,flip_left_over_vert);
-                let flip_long_side_fit = i32::max(flip_left_over_horiz,flip_left_over_vert);
-                if flip_short_side_fit < *best_short_side_fit || (flip_short_side_fit == *best_short_side_fit && flip_long_side_fit < *best_long_side_fit) {
-                    best_node.x = rect.x;
-                    best_node.y = rect.y;
-                    best_node.width = height;
-                    best_node.height = width;
-                    *best_short_side_fit = flip_short_side_fit;
-                    *best_long_side_fit = flip_long_side_fit;
-                }
-            }
-        }
-        best_node
-    }
-
-    fn find_best_long_side_fit(&mut self,width:i32,height:i32,best_short_side_fit:&mut i32,best_long_side_fit:&mut i32) -> Rect {
-        let mut best_node = Rect::default();
-        *best_long_side_fit = i32::max_value();
-        for free_rect in self.free_rect().iter() {
-            if free_rect.width >= width && free_rect.height >= height {
-                let left_over_horiz = i32::abs(free_rect.width - width);
-                let left_over_vert = i32::abs(free_rect.height - height);
-                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
-                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
-               if long_side_fit < *best_long_side_fit || (long_side_fit == *best_long_side_fit && short_side_fit < *best_short_side_fit) {
-                   best_node.x = free_rect.x;
-                   best_node.y = free_rect.y;
-                   best_node.width = width;
-                   best_node.height = height;
-                   *best_short_side_fit = short_side_fit;
-                   *best_long_side_fit = long_side_fit;
-               }
-            }
-            if self.allow_rotations && free_rect.width >= height && free_rect.height >= width {
-                let left_over_horiz = i32::abs(free_rect.width - height);
-                let left_over_vert = i32::abs(free_rect.height - width);
-                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
-                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
-                if long_side_fit < *best_long_side_fit || (long_side_fit == *best_long_side_fit && short_side_fit < *best_short_side_fit) {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = height;
-                    best_node.height = width;
-                    *best_short_side_fit = short_side_fit;
-                    *best_long_side_fit = long_side_fit;
-                }
-            }
-        }
-        best_node
-    }
-
-    fn find_bottom_left(&mut self,width:i32,height:i32,best_y:&mut i32,best_x:&mut i32) -> Rect {
-        let mut best_node = Rect::default();
-        *best_y = i32::max_value();
-        for free_rect in self.free_rect.iter() {
-            if free_rect.width >= width && free_rect.height >= height {
-                let top_side_y = free_rect.y + height;
-                if top_side_y < *best_y || (top_side_y == *best_y && free_rect.x < *best_x) {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = width;
-                    best_node.height = height;
-                    *best_y = top_side_y;
-                    *best_x = free_rect.x;
-                }
-
-                if self.allow_rotations && free_rect.width >= height && free_rect.height >= width {
-                    if top_side_y < *best_y || (top_side_y == *best_y && free_rect.x < *best_x) {
-                        best_node.x = free_rect.x;
-                        best_node.y = free_rect.y;
-                        best_node.width = height;
-                        best_node.height = width;
-                        *best_y = top_side_y;
-                        *best_x = free_rect.x;
-                    }
-                }
-            }
-        }
-        return best_node;
-    }
-    
-    fn common_interval_length(i1start:i32,i1end:i32,i2start:i32,i2end:i32) -> i32 {
-        if i1end < i2start || i2end < i1start {
-            return 0;
-        }
-        i32::min(i1end,i2end) - i32::max(i1start,i2start)
-    }
-
-    fn contact_point_score_node(&self,x:i32,y:i32,width:i32,height:i32) -> i32 {
-        let mut score = 0;
-        if x == 0 || x + width == (self.width as i32) {
-            score += height;
-        }
-        if y == 0 || y + height == self.height() as i32 {
-            score += width;
-        }
-        for use_rect in self.used_rect().iter() {
-            if use_rect.x == x + width  || use_rect.x + use_rect.width == x {
-                score += Self::common_interval_length(use_rect.y,use_rect.y + use_rect.height,y,y+height);
-            }
-            if use_rect.y == y + height  || use_rect.y + use_rect.height == y {
-                score += Self::common_interval_length(use_rect.x,use_rect.x + use_rect.width,x,x+width);
-            }
-        }
-        score
-    }
-
-    fn find_contact_point(&mut self,width:i32,height:i32,best_contact_score:&mut i32) -> Rect {
-        let mut best_node = Rect::default();
-        *best_contact_score = -1;
-        for free_rect in self.free_rect.iter() {
-            if free_rect.width >= width && free_rect.height >= height {
-                let score = self.contact_point_score_node(free_rect.x, free_rect.y,width,height);
-                if score > *best_contact_score {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = width;
-                    best_node.height = height;
-                    *best_contact_score = score;
-                }
-            }
-            if self.allow_rotations && free_rect.width >= height && free_rect.height >= width {
-                let score = self.contact_point_score_node(free_rect.x, free_rect.y,height,width);
-                if score > *best_contact_score {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = height;
-                    best_node.height = width;
-                    *best_contact_score = score;
-                }
-            }
-        }
-        best_node
-    }
-
-    fn find_best_area_fit(&mut self,width:i32,height:i32,best_area_fit:&mut i32,best_short_side_fit:&mut i32) -> Rect {
-        let mut best_node = Rect::default();
-        *best_area_fit = i32::max_value();
-        for free_rect in self.free_rect().iter() {
-            let area_fit = free_rect.width * free_rect.height - width * height;
-            if free_rect.width >= width && free_rect.height >= height {
-                let left_over_horiz = i32::abs(free_rect.width - width);
-                let left_over_vert = i32::abs(free_rect.height - height);
-                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
-                if area_fit < *best_area_fit || (area_fit == *best_area_fit && short_side_fit < *best_short_side_fit) {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = width;
-                    best_node.height = height;
-                    *best_short_side_fit = short_side_fit;
-                    *best_area_fit = area_fit;
-                }
-            }
-            if self.allow_rotations && free_rect.height >= width && free_rect.width > height {
-                let left_over_horiz = i32::abs(free_rect.width - height);
-                let left_over_vert = i32::abs(free_rect.height - width);
-                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
-                if area_fit < *best_area_fit || (area_fit == *best_area_fit && short_side_fit < *best_short_side_fit) {
-                    best_node.x = free_rect.x;
-                    best_node.y = free_rect.y;
-                    best_node.width = height;
-                    best_node.height = width;
-                    *best_short_side_fit = short_side_fit;
-                    *best_area_fit = area_fit;
-                }
-            }
-        }
-        best_node
-    }
-
-    fn split_free_node(&mut self,free_node:Rect,used_node:&Rect) -> bool {
-        if used_node.x >= free_node.x + free_node.width  ||
-           used_node.x + used_node.width <= free_node.x  || 
-           used_node.y >= free_node.y + free_node.height || 
-           used_node.y + used_node.height <= free_node.y {
-            return false
-        }
-        if used_node.x < free_node.x + free_node.width && used_node.x + used_node.width > free_node.x {
-            if used_node.y > free_node.y && used_node.y < free_node.y + free_node.height {
-                let mut new_rect = free_node.clone();
-                new_rect.height = used_node.y - new_rect.y;
-                self.free_rect.push(new_rect);
-            }
-
-            if used_node.y + used_node.height < free_node.y + free_node.height {
-                let mut new_node = free_node.clone();
-                new_node.y = used_node.y + used_node.height;
-                new_node.height = free_node.y + free_node.height - (used_node.y + used_node.height);
-                self.free_rect.push(new_node);
-            }
-        }
-
-        if used_node.y < free_node.y + free_node.height && used_node.y + used_node.height > free_node.y {
-            if used_node.x > free_node.x && used_node.x < free_node.x + free_node.width {
-                let mut new_node = free_node.clone();
-                new_node.width = used_node.x - new_node.x;
-                self.free_rect.push(new_node);
-            }
-            if used_node.x + used_node.width < free_node.x + free_node.width {
-                let mut new_node = free_node.clone();
-                new_node.x = used_node.x + used_node.width;
-                new_node.width = free_node.x + free_node.width - (used_node.x + used_node.width);
-                self.free_rect.push(new_node);
-            }
-        }
-        true
-    }
-
-    fn prune_free_list(&mut self) {
-        let mut i = 0;
-        while i < self.free_rect.len() {
-            let mut j = i + 1;
-            while j < self.free_rect.len() {
-                let ref_a = unsafe { self.free_rect.get_unchecked(i) };
-                let ref_b = unsafe { self.free_rect.get_unchecked(j) };
-                if Self::is_contained_in(ref_a,ref_b) {
-                    self.free_rect.remove(i);
-                    i-=1;
-                    break;
-                }
-                if Self::is_contained_in(ref_b,ref_a) {
-                    self.free_rect.remove(j);
-                    j-=1;
-                }
-                j += 1;
-            }
-            i += 1;
-        }
-    }
-
-    fn is_contained_in(a:&Rect,b:&Rect) -> bool {
-        return a.x >= b.x && a.y >= b.y && a.x + a.width <= b.x + b.width && a.y + a.height <= b.y + b.height;
-    }
-}
-
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Rect {
+   pub x:i32,
+   pub y:i32,
+   pub width:i32,
+   pub height:i32
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FreeRectChoiceHeuristic {
+    BestShortSideFit,
+    BestLongSideFit,
+    BestAreaFit,
+    BottomLeftRule,
+    ContactPointRule
+}
+
+impl FromStr for FreeRectChoiceHeuristic {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "best-short-side" | "BestShortSideFit" => Ok(FreeRectChoiceHeuristic::BestShortSideFit),
+            "best-long-side" | "BestLongSideFit" => Ok(FreeRectChoiceHeuristic::BestLongSideFit),
+            "best-area" | "BestAreaFit" => Ok(FreeRectChoiceHeuristic::BestAreaFit),
+            "bottom-left" | "BottomLeftRule" => Ok(FreeRectChoiceHeuristic::BottomLeftRule),
+            "contact-point" | "ContactPointRule" => Ok(FreeRectChoiceHeuristic::ContactPointRule),
+            _ => Err(format!("unknown heuristic {}",s))
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct MaxRectsBinPack {
+    width:u32,
+    height:u32,
+    allow_rotations:bool,
+    alignment:i32,
+    used_rect:Vec<Rect>,
+    free_rect:Vec<Rect>,
+    edges:EdgeIndex
+}
+
+// Placed rects by the coordinate of each of their sides, so contact scoring only visits the
+// rects that can touch a candidate instead of every placed rect.
+#[derive(Debug,Clone,Default)]
+struct EdgeIndex {
+    left:HashMap<i32,Vec<Rect>>,
+    right:HashMap<i32,Vec<Rect>>,
+    top:HashMap<i32,Vec<Rect>>,
+    bottom:HashMap<i32,Vec<Rect>>
+}
+
+impl EdgeIndex {
+    fn sides(&mut self,rect:&Rect) -> [(&mut HashMap<i32,Vec<Rect>>,i32);4] {
+        [(&mut self.left,rect.x),(&mut self.right,rect.x + rect.width),(&mut self.top,rect.y),(&mut self.bottom,rect.y + rect.height)]
+    }
+
+    fn add(&mut self,rect:&Rect) {
+        for (side,key) in self.sides(rect) {
+            side.entry(key).or_default().push(rect.clone());
+        }
+    }
+
+    fn remove(&mut self,rect:&Rect) {
+        for (side,key) in self.sides(rect) {
+            if let Some(rects) = side.get_mut(&key) {
+                if let Some(index) = rects.iter().position(|other| other == rect) {
+                    rects.swap_remove(index);
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = EdgeIndex::default();
+    }
+}
+
+impl Default for MaxRectsBinPack {
+    fn default() -> Self {
+        MaxRectsBinPack {
+            width:0,
+            height:0,
+            allow_rotations:true,
+            alignment:1,
+            used_rect:Vec::new(),
+            free_rect:Vec::new(),
+            edges:EdgeIndex::default()
+        }
+    }
+}
+
+impl MaxRectsBinPack {
+    pub fn new(width:u32,height:u32,rotations:bool) -> Self {
+        let mut max_rect = MaxRectsBinPack::default();
+        max_rect.init(width, height, rotations);
+        max_rect   
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Changes whether later inserts may rotate, e.g. to keep a single sprite upright.
+    pub fn set_allow_rotations(&mut self,rotations:bool) {
+        self.allow_rotations = rotations;
+    }
+
+    pub fn alignment(&self) -> u32 {
+        self.alignment as u32
+    }
+
+    /// Starts every later placement on a multiple of `alignment` and pads its size to a multiple
+    /// of it, so no two rects share an `alignment`-sized block (4 for BC/ETC compression).
+    /// Set it before placing anything.
+    pub fn set_alignment(&mut self,alignment:u32) {
+        self.alignment = i32::max(alignment as i32,1);
+    }
+
+    fn align_up(&self,value:i32) -> i32 {
+        (value + self.alignment - 1) / self.alignment * self.alignment
+    }
+
+    pub fn free_rect(&self) -> &Vec<Rect> {
+        &self.free_rect
+    }
+
+    pub fn used_rect(&self) -> &Vec<Rect> {
+        &self.used_rect
+    }
+
+    /// Total area of the placed rects.
+    pub fn used_area(&self) -> u64 {
+        self.used_rect.iter().map(|rect| rect.width as u64 * rect.height as u64).sum()
+    }
+
+    /// Area of the bin not covered by placed rects. Free rects overlap, so this is not their sum.
+    pub fn free_area(&self) -> u64 {
+        self.width as u64 * self.height as u64 - self.used_area()
+    }
+
+    /// Fraction of the bin covered by placed rects.
+    pub fn occupancy(&self) -> f32 {
+        if self.width == 0 || self.height == 0 {
+            return 0.0;
+        }
+        self.used_area() as f32 / (self.width as u64 * self.height as u64) as f32
+    }
+
+    pub fn largest_free_rect(&self) -> Option<&Rect> {
+        self.free_rect.iter().max_by_key(|rect| rect.width as u64 * rect.height as u64)
+    }
+
+    /// 0 when the free area is a single rectangle, approaching 1 as it splits into small pieces.
+    pub fn fragmentation(&self) -> f32 {
+        let free_area = self.free_area();
+        match self.largest_free_rect() {
+            Some(rect) if free_area > 0 => 1.0 - (rect.width as u64 * rect.height as u64) as f32 / free_area as f32,
+            _ => 0.0
+        }
+    }
+
+    pub(crate) fn free_rect_mut(&mut self) -> &mut Vec<Rect> {
+        &mut self.free_rect
+    }
+
+    pub fn init(&mut self,width:u32,height:u32,rotations:bool) {
+        self.width = width;
+        self.height = height;
+        self.allow_rotations = rotations;
+        self.free_rect.clear();
+        self.used_rect.clear();
+        self.edges.clear();
+
+        self.free_rect.push(Rect {x:0,y:0,width:width as i32,height:height as i32});
+    }
+
+    /// Places a `width`x`height` rect and returns it together with whether it was rotated
+    /// 90 degrees to fit. A rect with zero height means it didn't fit.
+    /// With an alignment, the returned rect keeps the requested size while the whole aligned block is taken.
+    pub fn insert(&mut self,width:i32,height:i32,method:FreeRectChoiceHeuristic) -> (Rect,bool) {
+        let mut score1 = 0;
+        let mut score2 = 0;
+        let (requested_width,requested_height) = (width,height);
+        let (width,height) = (self.align_up(width),self.align_up(height));
+        let (new_node,rotated) = match method {
+            FreeRectChoiceHeuristic::BestShortSideFit => {
+                self.find_best_short_side_fit(width, height,&mut score1,&mut score2)
+            },
+            FreeRectChoiceHeuristic::BottomLeftRule => {
+                self.find_bottom_left(width, height,&mut score1,&mut score2)
+            },
+            FreeRectChoiceHeuristic::ContactPointRule => {
+                self.find_contact_point(width, height,&mut score1)
+            },
+            FreeRectChoiceHeuristic::BestAreaFit => {
+                self.find_best_area_fit(width,height,&mut score1,&mut score2)
+            },
+            FreeRectChoiceHeuristic::BestLongSideFit => {
+                self.find_best_long_side_fit(width, height,&mut score1,&mut score2)
+            }
+        };
+        if new_node.height == 0 {
+            return (new_node,false);
+        }
+        self.place_rect(&new_node);
+        let (width,height) = if rotated { (requested_height,requested_width) } else { (requested_width,requested_height) };
+        (Rect {x:new_node.x,y:new_node.y,width,height},rotated)
+    }
+
+    /// Marks `rect` as occupied before packing, e.g. for a pinned sprite or a region that must stay empty.
+    /// Returns false when the rect is empty, leaves the bin or overlaps a placed rect.
+    pub fn reserve(&mut self,rect:Rect) -> bool {
+        if rect.width <= 0 || rect.height <= 0 || rect.x < 0 || rect.y < 0 ||
+           rect.x + rect.width > self.width as i32 || rect.y + rect.height > self.height as i32 {
+            return false;
+        }
+        // Widen the rect to whole alignment blocks so the free rects stay aligned.
+        let x = rect.x / self.alignment * self.alignment;
+        let y = rect.y / self.alignment * self.alignment;
+        let rect = Rect {
+            x,
+            y,
+            width:i32::min(self.align_up(rect.x + rect.width),self.width as i32) - x,
+            height:i32::min(self.align_up(rect.y + rect.height),self.height as i32) - y
+        };
+        if self.used_rect.iter().any(|used| Self::intersects(used,&rect)) {
+            return false;
+        }
+        self.place_rect(&rect);
+        true
+    }
+
+    /// Frees a rect returned by `insert` or passed to `reserve`, so the bin can be used as a
+    /// long-lived allocator such as a glyph cache. Any point of the placed rect identifies it,
+    /// so the returned rect works as a handle. The freed space is merged with the free space
+    /// around it. Returns false when no placed rect covers the top-left corner of `rect`.
+    pub fn remove(&mut self,rect:&Rect) -> bool {
+        let index = self.used_rect.iter().position(|used| {
+            used.x <= rect.x && rect.x < used.x + used.width && used.y <= rect.y && rect.y < used.y + used.height
+        });
+        match index {
+            Some(index) => {
+                let freed = self.used_rect.swap_remove(index);
+                self.edges.remove(&freed);
+                self.release_rect(&freed);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Frees every rect, keeping the size, rotation and alignment settings.
+    pub fn clear(&mut self) {
+        self.init(self.width, self.height, self.allow_rotations);
+    }
+
+    // Only the maximal free rects overlapping the freed area change. They are rebuilt by splitting
+    // the bin by the remaining used rects, dropping pieces that leave the freed area, and replace
+    // the old free rects they now contain.
+    fn release_rect(&mut self,freed:&Rect) {
+        let mut pieces = vec![Rect {x:0,y:0,width:self.width as i32,height:self.height as i32}];
+        for used in self.used_rect.iter() {
+            let mut split_pieces = Vec::with_capacity(pieces.len());
+            for piece in pieces.drain(..) {
+                if !Self::split_free_node(&piece,used,&mut split_pieces) {
+                    split_pieces.push(piece);
+                }
+            }
+            split_pieces.retain(|piece| Self::intersects(piece,freed));
+            Self::remove_contained(&mut split_pieces);
+            pieces = split_pieces;
+        }
+        self.free_rect.retain(|free| !pieces.iter().any(|piece| Self::is_contained_in(free,piece)));
+        self.free_rect.extend(pieces);
+    }
+
+    fn place_rect(&mut self,node:&Rect) {
+        let mut new_free_rect = Vec::new();
+        let mut i = 0;
+        while i < self.free_rect.len() {
+            if Self::split_free_node(&self.free_rect[i],node,&mut new_free_rect) {
+                self.free_rect.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.prune_free_list(new_free_rect);
+        self.edges.add(node);
+        self.used_rect.push(node.clone());
+    }
+
+    // A square looks the same either way, so it is never reported as rotated.
+    fn fits_rotated(&self,free_rect:&Rect,width:i32,height:i32) -> bool {
+        self.allow_rotations && width != height && free_rect.width >= height && free_rect.height >= width
+    }
+
+    fn find_best_short_side_fit(&mut self,width:i32,height:i32,best_short_side_fit:&mut i32,best_long_side_fit:&mut i32) -> (Rect,bool) {
+        let mut best_node = Rect::default();
+        let mut best_rotated = false;
+        *best_short_side_fit = i32::MAX;
+        *best_long_side_fit = i32::MAX;
+        for rect in self.free_rect.iter() {
+            if rect.width >= width && rect.height >= height {
+                let left_over_horiz = i32::abs(rect.width - width);
+                let left_over_vert = i32::abs(rect.height - height);
+                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
+                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
+                if short_side_fit < *best_short_side_fit || (short_side_fit == *best_short_side_fit && long_side_fit < *best_long_side_fit) {
+                    best_node.x = rect.x;
+                    best_node.y = rect.y;
+                    best_node.width = width;
+                    best_node.height = height;
+                    best_rotated = false;
+                    *best_short_side_fit = short_side_fit;
+                    *best_long_side_fit = long_side_fit; 
+                }
+            }
+            if self.fits_rotated(rect, width, height) {
+                let flip_left_over_horiz = i32::abs(rect.width - height);
+                let flip_left_over_vert = i32::abs(rect.height - width);
+                let flip_short_side_fit = i32::min(flip_left_over_horiz,flip_left_over_vert);
+                let flip_long_side_fit = i32::max(flip_left_over_horiz,flip_left_over_vert);
+                if flip_short_side_fit < *best_short_side_fit || (flip_short_side_fit == *best_short_side_fit && flip_long_side_fit < *best_long_side_fit) {
+                    best_node.x = rect.x;
+                    best_node.y = rect.y;
+                    best_node.width = height;
+                    best_node.height = width;
+                    best_rotated = true;
+                    *best_short_side_fit = flip_short_side_fit;
+                    *best_long_side_fit = flip_long_side_fit;
+                }
+            }
+        }
+        (best_node,best_rotated)
+    }
+
+    fn find_best_long_side_fit(&mut self,width:i32,height:i32,best_short_side_fit:&mut i32,best_long_side_fit:&mut i32) -> (Rect,bool) {
+        let mut best_node = Rect::default();
+        let mut best_rotated = false;
+        *best_short_side_fit = i32::MAX;
+        *best_long_side_fit = i32::MAX;
+        for free_rect in self.free_rect().iter() {
+            if free_rect.width >= width && free_rect.height >= height {
+                let left_over_horiz = i32::abs(free_rect.width - width);
+                let left_over_vert = i32::abs(free_rect.height - height);
+                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
+                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
+               if long_side_fit < *best_long_side_fit || (long_side_fit == *best_long_side_fit && short_side_fit < *best_short_side_fit) {
+                   best_node.x = free_rect.x;
+                   best_node.y = free_rect.y;
+                   best_node.width = width;
+                   best_node.height = height;
+                   best_rotated = false;
+                   *best_short_side_fit = short_side_fit;
+                   *best_long_side_fit = long_side_fit;
+               }
+            }
+            if self.fits_rotated(free_rect, width, height) {
+                let left_over_horiz = i32::abs(free_rect.width - height);
+                let left_over_vert = i32::abs(free_rect.height - width);
+                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
+                let long_side_fit = i32::max(left_over_horiz,left_over_vert);
+                if long_side_fit < *best_long_side_fit || (long_side_fit == *best_long_side_fit && short_side_fit < *best_short_side_fit) {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = height;
+                    best_node.height = width;
+                    best_rotated = true;
+                    *best_short_side_fit = short_side_fit;
+                    *best_long_side_fit = long_side_fit;
+                }
+            }
+        }
+        (best_node,best_rotated)
+    }
+
+    fn find_bottom_left(&mut self,width:i32,height:i32,best_y:&mut i32,best_x:&mut i32) -> (Rect,bool) {
+        let mut best_node = Rect::default();
+        let mut best_rotated = false;
+        *best_y = i32::MAX;
+        *best_x = i32::MAX;
+        for free_rect in self.free_rect.iter() {
+            if free_rect.width >= width && free_rect.height >= height {
+                let top_side_y = free_rect.y + height;
+                if top_side_y < *best_y || (top_side_y == *best_y && free_rect.x < *best_x) {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = width;
+                    best_node.height = height;
+                    best_rotated = false;
+                    *best_y = top_side_y;
+                    *best_x = free_rect.x;
+                }
+            }
+            if self.fits_rotated(free_rect, width, height) {
+                let top_side_y = free_rect.y + width;
+                if top_side_y < *best_y || (top_side_y == *best_y && free_rect.x < *best_x) {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = height;
+                    best_node.height = width;
+                    best_rotated = true;
+                    *best_y = top_side_y;
+                    *best_x = free_rect.x;
+                }
+            }
+        }
+        (best_node,best_rotated)
+    }
+    
+    fn common_interval_length(i1start:i32,i1end:i32,i2start:i32,i2end:i32) -> i32 {
+        if i1end < i2start || i2end < i1start {
+            return 0;
+        }
+        i32::min(i1end,i2end) - i32::max(i1start,i2start)
+    }
+
+    fn contact_point_score_node(&self,x:i32,y:i32,width:i32,height:i32) -> i32 {
+        let mut score = 0;
+        if x == 0 || x + width == (self.width as i32) {
+            score += height;
+        }
+        if y == 0 || y + height == self.height() as i32 {
+            score += width;
+        }
+        let beside = self.edges.left.get(&(x + width)).into_iter().chain(self.edges.right.get(&x)).flatten();
+        for use_rect in beside {
+            score += Self::common_interval_length(use_rect.y,use_rect.y + use_rect.height,y,y+height);
+        }
+        let above_or_below = self.edges.top.get(&(y + height)).into_iter().chain(self.edges.bottom.get(&y)).flatten();
+        for use_rect in above_or_below {
+            score += Self::common_interval_length(use_rect.x,use_rect.x + use_rect.width,x,x+width);
+        }
+        score
+    }
+
+    fn find_contact_point(&mut self,width:i32,height:i32,best_contact_score:&mut i32) -> (Rect,bool) {
+        let mut best_node = Rect::default();
+        let mut best_rotated = false;
+        *best_contact_score = -1;
+        for free_rect in self.free_rect.iter() {
+            if free_rect.width >= width && free_rect.height >= height {
+                let score = self.contact_point_score_node(free_rect.x, free_rect.y,width,height);
+                if score > *best_contact_score {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = width;
+                    best_node.height = height;
+                    best_rotated = false;
+                    *best_contact_score = score;
+                }
+            }
+            if self.fits_rotated(free_rect, width, height) {
+                let score = self.contact_point_score_node(free_rect.x, free_rect.y,height,width);
+                if score > *best_contact_score {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = height;
+                    best_node.height = width;
+                    best_rotated = true;
+                    *best_contact_score = score;
+                }
+            }
+        }
+        (best_node,best_rotated)
+    }
+
+    fn find_best_area_fit(&mut self,width:i32,height:i32,best_area_fit:&mut i32,best_short_side_fit:&mut i32) -> (Rect,bool) {
+        let mut best_node = Rect::default();
+        let mut best_rotated = false;
+        *best_area_fit = i32::MAX;
+        *best_short_side_fit = i32::MAX;
+        for free_rect in self.free_rect().iter() {
+            let area_fit = free_rect.width * free_rect.height - width * height;
+            if free_rect.width >= width && free_rect.height >= height {
+                let left_over_horiz = i32::abs(free_rect.width - width);
+                let left_over_vert = i32::abs(free_rect.height - height);
+                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
+                if area_fit < *best_area_fit || (area_fit == *best_area_fit && short_side_fit < *best_short_side_fit) {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = width;
+                    best_node.height = height;
+                    best_rotated = false;
+                    *best_short_side_fit = short_side_fit;
+                    *best_area_fit = area_fit;
+                }
+            }
+            if self.fits_rotated(free_rect, width, height) {
+                let left_over_horiz = i32::abs(free_rect.width - height);
+                let left_over_vert = i32::abs(free_rect.height - width);
+                let short_side_fit = i32::min(left_over_horiz,left_over_vert);
+                if area_fit < *best_area_fit || (area_fit == *best_area_fit && short_side_fit < *best_short_side_fit) {
+                    best_node.x = free_rect.x;
+                    best_node.y = free_rect.y;
+                    best_node.width = height;
+                    best_node.height = width;
+                    best_rotated = true;
+                    *best_short_side_fit = short_side_fit;
+                    *best_area_fit = area_fit;
+                }
+            }
+        }
+        (best_node,best_rotated)
+    }
+
+    // Pushes the parts of `free_node` left uncovered by `used_node` and returns whether they overlap.
+    fn split_free_node(free_node:&Rect,used_node:&Rect,new_free_rect:&mut Vec<Rect>) -> bool {
+        if used_node.x >= free_node.x + free_node.width  ||
+           used_node.x + used_node.width <= free_node.x  || 
+           used_node.y >= free_node.y + free_node.height || 
+           used_node.y + used_node.height <= free_node.y {
+            return false
+        }
+        if used_node.x < free_node.x + free_node.width && used_node.x + used_node.width > free_node.x {
+            if used_node.y > free_node.y && used_node.y < free_node.y + free_node.height {
+                let mut new_rect = free_node.clone();
+                new_rect.height = used_node.y - new_rect.y;
+                new_free_rect.push(new_rect);
+            }
+
+            if used_node.y + used_node.height < free_node.y + free_node.height {
+                let mut new_node = free_node.clone();
+                new_node.y = used_node.y + used_node.height;
+                new_node.height = free_node.y + free_node.height - (used_node.y + used_node.height);
+                new_free_rect.push(new_node);
+            }
+        }
+
+        if used_node.y < free_node.y + free_node.height && used_node.y + used_node.height > free_node.y {
+            if used_node.x > free_node.x && used_node.x < free_node.x + free_node.width {
+                let mut new_node = free_node.clone();
+                new_node.width = used_node.x - new_node.x;
+                new_free_rect.push(new_node);
+            }
+            if used_node.x + used_node.width < free_node.x + free_node.width {
+                let mut new_node = free_node.clone();
+                new_node.x = used_node.x + used_node.width;
+                new_node.width = free_node.x + free_node.width - (used_node.x + used_node.width);
+                new_free_rect.push(new_node);
+            }
+        }
+        true
+    }
+
+    // The existing free rects were already pruned against each other, and a rect split off
+    // one of them can't contain another, so only the new rects need to be checked.
+    fn prune_free_list(&mut self,mut new_free_rect:Vec<Rect>) {
+        Self::remove_contained(&mut new_free_rect);
+        for rect in new_free_rect {
+            if !self.free_rect.iter().any(|free_rect| Self::is_contained_in(&rect,free_rect)) {
+                self.free_rect.push(rect);
+            }
+        }
+    }
+
+    fn remove_contained(rects:&mut Vec<Rect>) {
+        let mut i = 0;
+        while i < rects.len() {
+            let mut j = i + 1;
+            let mut removed = false;
+            while j < rects.len() {
+                if Self::is_contained_in(&rects[i],&rects[j]) {
+                    rects.swap_remove(i);
+                    removed = true;
+                    break;
+                }
+                if Self::is_contained_in(&rects[j],&rects[i]) {
+                    rects.swap_remove(j);
+                } else {
+                    j += 1;
+                }
+            }
+            if !removed {
+                i += 1;
+            }
+        }
+    }
+
+    fn intersects(a:&Rect,b:&Rect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    fn is_contained_in(a:&Rect,b:&Rect) -> bool {
+        a.x >= b.x && a.y >= b.y && a.x + a.width <= b.x + b.width && a.y + a.height <= b.y + b.height
+    }
+}
+
@@ -0,0 +1,221 @@
+use crate::guillotine::{self,GuillotineBinPack};
+use crate::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack,Rect};
+use crate::shelf::{ShelfBinPack,ShelfHeuristic};
+use crate::skyline::{SkylineBinPack,SkylineHeuristic};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PackerKind {
+    MaxRects,
+    Skyline,
+    Guillotine,
+    Shelf
+}
+
+impl FromStr for PackerKind {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "maxrects" | "MaxRects" => Ok(PackerKind::MaxRects),
+            "skyline" | "Skyline" => Ok(PackerKind::Skyline),
+            "guillotine" | "Guillotine" => Ok(PackerKind::Guillotine),
+            "shelf" | "Shelf" => Ok(PackerKind::Shelf),
+            _ => Err(format!("unknown packer {}",s))
+        }
+    }
+}
+
+/// The order sprites are handed to the packer in. Every order except `None` puts the
+/// biggest sprites first; ties keep the input order.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SortOrder {
+    None,
+    Area,
+    Perimeter,
+    MaxSide,
+    Width,
+    Height
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "none" | "None" => Ok(SortOrder::None),
+            "area" | "Area" => Ok(SortOrder::Area),
+            "perimeter" | "Perimeter" => Ok(SortOrder::Perimeter),
+            "max-side" | "MaxSide" => Ok(SortOrder::MaxSide),
+            "width" | "Width" => Ok(SortOrder::Width),
+            "height" | "Height" => Ok(SortOrder::Height),
+            _ => Err(format!("unknown sort order {}",s))
+        }
+    }
+}
+
+impl SortOrder {
+    /// Returns the indices of `sizes` in insertion order.
+    pub fn sort(self,sizes:&[(u32,u32)]) -> Vec<usize> {
+        let mut order:Vec<usize> = (0..sizes.len()).collect();
+        let key = |(w,h):(u32,u32)| -> u64 {
+            match self {
+                SortOrder::None => 0,
+                SortOrder::Area => w as u64 * h as u64,
+                SortOrder::Perimeter => w as u64 + h as u64,
+                SortOrder::MaxSide => u32::max(w, h) as u64,
+                SortOrder::Width => w as u64,
+                SortOrder::Height => h as u64
+            }
+        };
+        order.sort_by_key(|&i| std::cmp::Reverse(key(sizes[i])));
+        order
+    }
+}
+
+/// Common interface over the bin packing algorithms used by `sprite_sheet_gen`.
+#[derive(Debug,Clone)]
+pub enum Packer {
+    MaxRects(MaxRectsBinPack,FreeRectChoiceHeuristic),
+    Skyline(SkylineBinPack,SkylineHeuristic),
+    /// The bin, free rect choice, split rule and whether to merge free rects.
+    Guillotine(GuillotineBinPack,guillotine::RectChoiceHeuristic,guillotine::SplitHeuristic,bool),
+    Shelf(ShelfBinPack,ShelfHeuristic)
+}
+
+impl Packer {
+    /// Empty bins for every heuristic combination of every packer, used to search for the densest layout.
+    pub fn candidates(width:u32,height:u32,rotations:bool,use_waste_map:bool,guillotine_merge:bool) -> Vec<Packer> {
+        let mut candidates = Vec::new();
+        for &method in [FreeRectChoiceHeuristic::BestShortSideFit,FreeRectChoiceHeuristic::BestLongSideFit,FreeRectChoiceHeuristic::BestAreaFit,
+                        FreeRectChoiceHeuristic::BottomLeftRule,FreeRectChoiceHeuristic::ContactPointRule].iter() {
+            candidates.push(Packer::MaxRects(MaxRectsBinPack::new(width, height, rotations),method));
+        }
+        for &method in [SkylineHeuristic::BottomLeft,SkylineHeuristic::MinWasteFit].iter() {
+            candidates.push(Packer::Skyline(SkylineBinPack::new(width, height, rotations, use_waste_map),method));
+        }
+        let choices = [guillotine::RectChoiceHeuristic::BestAreaFit,guillotine::RectChoiceHeuristic::BestShortSideFit,guillotine::RectChoiceHeuristic::BestLongSideFit,
+                       guillotine::RectChoiceHeuristic::WorstAreaFit,guillotine::RectChoiceHeuristic::WorstShortSideFit,guillotine::RectChoiceHeuristic::WorstLongSideFit];
+        let splits = [guillotine::SplitHeuristic::ShorterLeftoverAxis,guillotine::SplitHeuristic::LongerLeftoverAxis,guillotine::SplitHeuristic::MinimizeArea,
+                      guillotine::SplitHeuristic::MaximizeArea,guillotine::SplitHeuristic::ShorterAxis,guillotine::SplitHeuristic::LongerAxis];
+        for &choice in choices.iter() {
+            for &split in splits.iter() {
+                candidates.push(Packer::Guillotine(GuillotineBinPack::new(width, height, rotations),choice,split,guillotine_merge));
+            }
+        }
+        for &method in [ShelfHeuristic::NextFit,ShelfHeuristic::FirstFit,ShelfHeuristic::BestHeightFit].iter() {
+            candidates.push(Packer::Shelf(ShelfBinPack::new(width, height, rotations, use_waste_map),method));
+        }
+        candidates
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            Packer::MaxRects(bin,_) => bin.width(),
+            Packer::Skyline(bin,_) => bin.width(),
+            Packer::Guillotine(bin,..) => bin.width(),
+            Packer::Shelf(bin,_) => bin.width()
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Packer::MaxRects(bin,_) => bin.height(),
+            Packer::Skyline(bin,_) => bin.height(),
+            Packer::Guillotine(bin,..) => bin.height(),
+            Packer::Shelf(bin,_) => bin.height()
+        }
+    }
+
+    pub fn used_rect(&self) -> &Vec<Rect> {
+        match self {
+            Packer::MaxRects(bin,_) => bin.used_rect(),
+            Packer::Skyline(bin,_) => bin.used_rect(),
+            Packer::Guillotine(bin,..) => bin.used_rect(),
+            Packer::Shelf(bin,_) => bin.used_rect()
+        }
+    }
+
+    pub fn set_allow_rotations(&mut self,rotations:bool) {
+        match self {
+            Packer::MaxRects(bin,_) => bin.set_allow_rotations(rotations),
+            Packer::Skyline(bin,_) => bin.set_allow_rotations(rotations),
+            Packer::Guillotine(bin,..) => bin.set_allow_rotations(rotations),
+            Packer::Shelf(bin,_) => bin.set_allow_rotations(rotations)
+        }
+    }
+
+    /// Marks `rect` as occupied. Only the MaxRects packer supports this; the others return false.
+    pub fn reserve(&mut self,rect:Rect) -> bool {
+        match self {
+            Packer::MaxRects(bin,_) => bin.reserve(rect),
+            _ => false
+        }
+    }
+
+    /// Aligns later placements to multiples of `alignment`. Only the MaxRects packer supports this;
+    /// the others return false for any alignment above 1.
+    pub fn set_alignment(&mut self,alignment:u32) -> bool {
+        match self {
+            Packer::MaxRects(bin,_) => {
+                bin.set_alignment(alignment);
+                true
+            },
+            _ => alignment <= 1
+        }
+    }
+
+    /// Places a `width`x`height` rectangle and returns it with whether it was rotated 90 degrees.
+    /// A rect with zero height means it didn't fit.
+    pub fn insert(&mut self,width:i32,height:i32) -> (Rect,bool) {
+        let rect = match self {
+            Packer::MaxRects(bin,method) => return bin.insert(width, height, *method),
+            Packer::Skyline(bin,method) => bin.insert(width, height, *method),
+            Packer::Guillotine(bin,rect_choice,split_method,merge) => {
+                bin.insert(width, height, *merge, *rect_choice, *split_method)
+            },
+            Packer::Shelf(bin,method) => bin.insert(width, height, *method)
+        };
+        // These packers only turn rectangles that change shape when turned.
+        let rotated = rect.height > 0 && rect.width != width;
+        (rect,rotated)
+    }
+}
+
+impl fmt::Display for Packer {
+    fn fmt(&self,f:&mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packer::MaxRects(_,method) => write!(f,"maxrects {:?}",method),
+            Packer::Skyline(_,method) => write!(f,"skyline {:?}",method),
+            Packer::Guillotine(_,rect_choice,split_method,_) => write!(f,"guillotine {:?} {:?}",rect_choice,split_method),
+            Packer::Shelf(_,method) => write!(f,"shelf {:?}",method)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Packer;
+
+    #[test]
+    fn test_candidates_insert() {
+        // Largest first, as the sheet generator feeds them; a guillotine split early on can't fit 1000x198 later.
+        let test_data = [(1000,198),(300,20),(20,300),(128,45),(100,100),(44,89),(50,50),(32,32),(27,15),(32,12)];
+        for &(rotations,waste_map,merge) in [(false,false,false),(true,false,false),(false,true,true),(true,true,true)].iter() {
+            for mut packer in Packer::candidates(1024,1024,rotations,waste_map,merge) {
+                for &(w,h) in test_data.iter() {
+                    let (rect,rotated) = packer.insert(w,h);
+                    assert!(rect.height > 0,"{} didn't place {}x{}",packer,w,h);
+                    assert_eq!((rect.width,rect.height),if rotated {(h,w)} else {(w,h)});
+                    assert!(rect.x >= 0 && rect.y >= 0 && rect.x + rect.width <= 1024 && rect.y + rect.height <= 1024);
+                }
+                let used = packer.used_rect();
+                assert_eq!(used.len(),test_data.len());
+                for (i,a) in used.iter().enumerate() {
+                    for b in used[i + 1..].iter() {
+                        assert!(a.x >= b.x + b.width || b.x >= a.x + a.width || a.y >= b.y + b.height || b.y >= a.y + a.height,"{} overlaps",packer);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use image::RgbaImage;
+use std::path::Path;
+
+/// A visible top-level layer (or flattened group) of a PSD document.
+pub struct PsdLayer {
+    pub name:String,
+    pub left:i32,
+    pub top:i32,
+    pub image:RgbaImage
+}
+
+struct LayerRecord {
+    name:String,
+    top:i32,
+    left:i32,
+    bottom:i32,
+    right:i32,
+    channels:Vec<(i16,usize)>,
+    opacity:u8,
+    visible:bool,
+    section:u32
+}
+
+impl LayerRecord {
+    fn width(&self) -> u32 {
+        i32::max(self.right - self.left,0) as u32
+    }
+
+    fn height(&self) -> u32 {
+        i32::max(self.bottom - self.top,0) as u32
+    }
+}
+
+struct Reader<'a> {
+    data:&'a [u8],
+    pos:usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self,len:usize) -> Result<&'a [u8],String> {
+        if self.pos + len > self.data.len() {
+            return Err(String::from("unexpected end of psd"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8,String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16,String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0],b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32,String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0],b[1],b[2],b[3]]))
+    }
+
+    fn i16(&mut self) -> Result<i16,String> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn i32(&mut self) -> Result<i32,String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn section(&mut self) -> Result<Reader<'a>,String> {
+        let len = self.u32()? as usize;
+        Ok(Reader {data:self.bytes(len)?,pos:0})
+    }
+}
+
+pub fn load_layers(path:&Path) -> Result<Vec<PsdLayer>,String> {
+    let data = std::fs::read(path).map_err(|_| String::from("can't read psd file"))?;
+    parse_layers(&data)
+}
+
+/// Parses an 8-bit RGB PSD and returns its visible top-level layers, top-most first.
+/// Groups are flattened into a single layer named after the group.
+pub fn parse_layers(data:&[u8]) -> Result<Vec<PsdLayer>,String> {
+    let mut reader = Reader {data,pos:0};
+    if reader.bytes(4)? != b"8BPS" {
+        return Err(String::from("not a psd file"));
+    }
+    if reader.u16()? != 1 {
+        return Err(String::from("only psd version 1 is supported"));
+    }
+    reader.bytes(6)?;
+    let _channels = reader.u16()?;
+    let _height = reader.u32()?;
+    let _width = reader.u32()?;
+    let depth = reader.u16()?;
+    let color_mode = reader.u16()?;
+    if depth != 8 || color_mode != 3 {
+        return Err(String::from("only 8-bit RGB psd is supported"));
+    }
+    reader.section()?;
+    reader.section()?;
+    let mut layer_and_mask = reader.section()?;
+    if layer_and_mask.data.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut layer_info = layer_and_mask.section()?;
+    if layer_info.data.is_empty() {
+        return Ok(vec![]);
+    }
+    let count = layer_info.i16()?.unsigned_abs() as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        records.push(read_layer_record(&mut layer_info)?);
+    }
+
+    let mut stack:Vec<Vec<PsdLayer>> = vec![vec![]];
+    for record in records.iter() {
+        let image = read_layer_image(&mut layer_info,record)?;
+        match record.section {
+            3 => stack.push(vec![]),
+            1 | 2 => {
+                let children = stack.pop().ok_or_else(|| String::from("unbalanced layer groups"))?;
+                let parent = stack.last_mut().ok_or_else(|| String::from("unbalanced layer groups"))?;
+                if record.visible {
+                    if let Some(mut group) = flatten_group(&record.name,children) {
+                        apply_opacity(&mut group.image,record.opacity);
+                        parent.push(group);
+                    }
+                }
+            },
+            _ => {
+                if record.visible && record.width() > 0 && record.height() > 0 {
+                    let parent = stack.last_mut().ok_or_else(|| String::from("unbalanced layer groups"))?;
+                    parent.push(PsdLayer {name:record.name.clone(),left:record.left,top:record.top,image});
+                }
+            }
+        }
+    }
+    if stack.len() != 1 {
+        return Err(String::from("unbalanced layer groups"));
+    }
+    let mut layers = stack.pop().unwrap();
+    layers.reverse();
+    Ok(layers)
+}
+
+fn read_layer_record(reader:&mut Reader) -> Result<LayerRecord,String> {
+    let top = reader.i32()?;
+    let left = reader.i32()?;
+    let bottom = reader.i32()?;
+    let right = reader.i32()?;
+    let channel_count = reader.u16()?;
+    let mut channels = Vec::with_capacity(channel_count as usize);
+    for _ in 0..channel_count {
+        let id = reader.i16()?;
+        let len = reader.u32()? as usize;
+        channels.push((id,len));
+    }
+    if reader.bytes(4)? != b"8BIM" {
+        return Err(String::from("bad layer blend signature"));
+    }
+    reader.bytes(4)?;
+    let opacity = reader.u8()?;
+    let _clipping = reader.u8()?;
+    let flags = reader.u8()?;
+    reader.u8()?;
+    let mut extra = reader.section()?;
+    extra.section()?;
+    extra.section()?;
+    let name_len = extra.u8()? as usize;
+    let mut name = String::from_utf8_lossy(extra.bytes(name_len)?).into_owned();
+    let name_pad = (4 - (name_len + 1) % 4) % 4;
+    extra.bytes(name_pad)?;
+
+    let mut section = 0;
+    while extra.pos + 12 <= extra.data.len() {
+        extra.bytes(4)?;
+        let key = extra.bytes(4)?;
+        let mut info = extra.section()?;
+        match key {
+            b"luni" => {
+                let char_count = info.u32()? as usize;
+                let mut units = Vec::with_capacity(char_count);
+                for _ in 0..char_count {
+                    units.push(info.u16()?);
+                }
+                name = String::from_utf16_lossy(&units).trim_end_matches('\0').to_string();
+            },
+            b"lsct" | b"lsdk" => {
+                section = info.u32()?;
+            },
+            _ => {}
+        }
+    }
+
+    Ok(LayerRecord {
+        name,
+        top,
+        left,
+        bottom,
+        right,
+        channels,
+        opacity,
+        visible:flags & 0x02 == 0,
+        section
+    })
+}
+
+fn read_layer_image(reader:&mut Reader,record:&LayerRecord) -> Result<RgbaImage,String> {
+    let (width,height) = (record.width(),record.height());
+    let mut image = RgbaImage::new(width,height);
+    let mut has_alpha = false;
+    for (id,len) in record.channels.iter() {
+        let data = reader.bytes(*len)?;
+        let offset = match *id {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            -1 => 3,
+            _ => continue
+        };
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let plane = decode_channel(data,width as usize,height as usize)?;
+        for (pixel,value) in image.pixels_mut().zip(plane) {
+            pixel.0[offset] = value;
+        }
+        has_alpha |= offset == 3;
+    }
+    if !has_alpha {
+        for pixel in image.pixels_mut() {
+            pixel.0[3] = 255;
+        }
+    }
+    apply_opacity(&mut image,record.opacity);
+    Ok(image)
+}
+
+fn decode_channel(data:&[u8],width:usize,height:usize) -> Result<Vec<u8>,String> {
+    let mut reader = Reader {data,pos:0};
+    match reader.u16()? {
+        0 => Ok(reader.bytes(width * height)?.to_vec()),
+        1 => {
+            let mut row_lens = Vec::with_capacity(height);
+            for _ in 0..height {
+                row_lens.push(reader.u16()? as usize);
+            }
+            let mut plane = Vec::with_capacity(width * height);
+            for row_len in row_lens {
+                let row = unpack_bits(reader.bytes(row_len)?)?;
+                if row.len() != width {
+                    return Err(String::from("bad rle row length"));
+                }
+                plane.extend_from_slice(&row);
+            }
+            Ok(plane)
+        },
+        _ => Err(String::from("zip compressed psd layers are not supported"))
+    }
+}
+
+fn unpack_bits(data:&[u8]) -> Result<Vec<u8>,String> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let n = data[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let count = n as usize + 1;
+            if i + count > data.len() {
+                return Err(String::from("bad rle data"));
+            }
+            out.extend_from_slice(&data[i..i + count]);
+            i += count;
+        } else if n != -128 {
+            let value = *data.get(i).ok_or_else(|| String::from("bad rle data"))?;
+            out.extend(std::iter::repeat_n(value,(1 - n as isize) as usize));
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+fn apply_opacity(image:&mut RgbaImage,opacity:u8) {
+    if opacity == 255 {
+        return;
+    }
+    for pixel in image.pixels_mut() {
+        pixel.0[3] = (pixel.0[3] as u32 * opacity as u32 / 255) as u8;
+    }
+}
+
+fn flatten_group(name:&str,children:Vec<PsdLayer>) -> Option<PsdLayer> {
+    let left = children.iter().map(|l| l.left).min()?;
+    let top = children.iter().map(|l| l.top).min()?;
+    let right = children.iter().map(|l| l.left + l.image.width() as i32).max()?;
+    let bottom = children.iter().map(|l| l.top + l.image.height() as i32).max()?;
+    let mut image = RgbaImage::new((right - left) as u32,(bottom - top) as u32);
+    for child in children.iter() {
+        image::imageops::overlay(&mut image,&child.image,(child.left - left) as u32,(child.top - top) as u32);
+    }
+    Some(PsdLayer {name:String::from(name),left,top,image})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_layers;
+
+    struct TestLayer {
+        name:&'static str,
+        rect:(i32,i32,i32,i32),
+        color:[u8;4],
+        hidden:bool,
+        section:Option<u32>
+    }
+
+    fn layer(name:&'static str,rect:(i32,i32,i32,i32),color:[u8;4]) -> TestLayer {
+        TestLayer {name,rect,color,hidden:false,section:None}
+    }
+
+    fn write_section(out:&mut Vec<u8>,data:&[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    fn build_psd(layers:&[TestLayer]) -> Vec<u8> {
+        let mut records = Vec::new();
+        let mut channel_data = Vec::new();
+        records.extend_from_slice(&(layers.len() as i16).to_be_bytes());
+        for layer in layers {
+            let (left,top,right,bottom) = layer.rect;
+            for v in [top,left,bottom,right].iter() {
+                records.extend_from_slice(&v.to_be_bytes());
+            }
+            let pixel_count = ((right - left) * (bottom - top)) as usize;
+            records.extend_from_slice(&4u16.to_be_bytes());
+            for (id,value) in [(0i16,layer.color[0]),(1,layer.color[1]),(2,layer.color[2]),(-1,layer.color[3])].iter() {
+                records.extend_from_slice(&id.to_be_bytes());
+                records.extend_from_slice(&((pixel_count + 2) as u32).to_be_bytes());
+                channel_data.extend_from_slice(&0u16.to_be_bytes());
+                channel_data.extend(std::iter::repeat_n(*value,pixel_count));
+            }
+            records.extend_from_slice(b"8BIMnorm");
+            records.extend_from_slice(&[255,0,if layer.hidden {2} else {0},0]);
+            let mut extra = Vec::new();
+            write_section(&mut extra,&[]);
+            write_section(&mut extra,&[]);
+            extra.push(layer.name.len() as u8);
+            extra.extend_from_slice(layer.name.as_bytes());
+            while (extra.len() - 8) % 4 != 0 {
+                extra.push(0);
+            }
+            if let Some(section) = layer.section {
+                extra.extend_from_slice(b"8BIMlsct");
+                write_section(&mut extra,&section.to_be_bytes());
+            }
+            write_section(&mut records,&extra);
+        }
+        records.extend_from_slice(&channel_data);
+        let mut layer_info = Vec::new();
+        write_section(&mut layer_info,&records);
+
+        let mut psd = Vec::new();
+        psd.extend_from_slice(b"8BPS");
+        psd.extend_from_slice(&1u16.to_be_bytes());
+        psd.extend_from_slice(&[0;6]);
+        psd.extend_from_slice(&3u16.to_be_bytes());
+        psd.extend_from_slice(&64u32.to_be_bytes());
+        psd.extend_from_slice(&64u32.to_be_bytes());
+        psd.extend_from_slice(&8u16.to_be_bytes());
+        psd.extend_from_slice(&3u16.to_be_bytes());
+        write_section(&mut psd,&[]);
+        write_section(&mut psd,&[]);
+        write_section(&mut psd,&layer_info);
+        psd
+    }
+
+    #[test]
+    fn test_visible_layers() {
+        let mut hidden = layer("hidden",(0,0,4,4),[0,0,255,255]);
+        hidden.hidden = true;
+        let psd = build_psd(&[layer("bg",(0,0,8,8),[255,0,0,255]),hidden,layer("icon",(10,20,13,22),[0,255,0,128])]);
+        let layers = parse_layers(&psd).unwrap();
+        assert_eq!(layers.len(),2);
+        assert_eq!(layers[0].name,"icon");
+        assert_eq!((layers[0].left,layers[0].top),(10,20));
+        assert_eq!(layers[0].image.dimensions(),(3,2));
+        assert_eq!(layers[0].image.get_pixel(2,1).0,[0,255,0,128]);
+        assert_eq!(layers[1].name,"bg");
+    }
+
+    #[test]
+    fn test_group_flattened() {
+        let mut divider = layer("</Layer group>",(0,0,0,0),[0;4]);
+        divider.section = Some(3);
+        let mut folder = layer("button",(0,0,0,0),[0;4]);
+        folder.section = Some(1);
+        let psd = build_psd(&[divider,layer("back",(4,4,12,8),[255,0,0,255]),layer("label",(6,2,8,6),[0,0,255,255]),folder]);
+        let layers = parse_layers(&psd).unwrap();
+        assert_eq!(layers.len(),1);
+        assert_eq!(layers[0].name,"button");
+        assert_eq!((layers[0].left,layers[0].top),(4,2));
+        assert_eq!(layers[0].image.dimensions(),(8,6));
+        assert_eq!(layers[0].image.get_pixel(2,2).0,[0,0,255,255]);
+        assert_eq!(layers[0].image.get_pixel(0,2).0,[255,0,0,255]);
+        assert_eq!(layers[0].image.get_pixel(0,0).0,[0,0,0,0]);
+    }
+}
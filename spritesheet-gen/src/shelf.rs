@@ -0,0 +1,222 @@
+use crate::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack,Rect};
+use std::str::FromStr;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ShelfHeuristic {
+    /// Only the newest shelf is considered, so every insert is O(1).
+    NextFit,
+    /// The first shelf the rectangle fits on is used.
+    FirstFit,
+    /// The shelf whose height is closest to the rectangle height is used.
+    BestHeightFit
+}
+
+impl FromStr for ShelfHeuristic {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "next-fit" | "NextFit" => Ok(ShelfHeuristic::NextFit),
+            "first-fit" | "FirstFit" => Ok(ShelfHeuristic::FirstFit),
+            "best-height" | "BestHeightFit" => Ok(ShelfHeuristic::BestHeightFit),
+            _ => Err(format!("unknown shelf heuristic {}",s))
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+struct Shelf {
+    current_x:i32,
+    start_y:i32,
+    height:i32,
+    used_rect:Vec<Rect>
+}
+
+/// Shelf bin packer. Rectangles are placed left to right on horizontal shelves,
+/// which is fast and tight for sprites of near-identical height such as glyphs.
+/// With a waste map, the space left above short rectangles of a closed shelf is reused.
+#[derive(Debug,Clone)]
+pub struct ShelfBinPack {
+    width:u32,
+    height:u32,
+    allow_rotations:bool,
+    shelves:Vec<Shelf>,
+    used_rect:Vec<Rect>,
+    waste_map:Option<MaxRectsBinPack>
+}
+
+impl ShelfBinPack {
+    pub fn new(width:u32,height:u32,rotations:bool,use_waste_map:bool) -> Self {
+        let mut shelf = ShelfBinPack {
+            width:0,
+            height:0,
+            allow_rotations:rotations,
+            shelves:Vec::new(),
+            used_rect:Vec::new(),
+            waste_map:None
+        };
+        shelf.init(width, height, rotations, use_waste_map);
+        shelf
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn used_rect(&self) -> &Vec<Rect> {
+        &self.used_rect
+    }
+
+    pub fn set_allow_rotations(&mut self,rotations:bool) {
+        self.allow_rotations = rotations;
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            waste_map.set_allow_rotations(rotations);
+        }
+    }
+
+    pub fn init(&mut self,width:u32,height:u32,rotations:bool,use_waste_map:bool) {
+        self.width = width;
+        self.height = height;
+        self.allow_rotations = rotations;
+        self.used_rect.clear();
+        self.shelves.clear();
+        self.shelves.push(Shelf {current_x:0,start_y:0,height:0,used_rect:Vec::new()});
+        self.waste_map = if use_waste_map {
+            let mut waste_map = MaxRectsBinPack::new(width, height, rotations);
+            waste_map.free_rect_mut().clear();
+            Some(waste_map)
+        } else {
+            None
+        };
+    }
+
+    pub fn insert(&mut self,width:i32,height:i32,method:ShelfHeuristic) -> Rect {
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            let (node,_) = waste_map.insert(width, height, FreeRectChoiceHeuristic::BestShortSideFit);
+            if node.height != 0 {
+                self.used_rect.push(node.clone());
+                return node;
+            }
+        }
+        let last = self.shelves.len() - 1;
+        let shelf_index = match method {
+            ShelfHeuristic::NextFit => {
+                if self.fits_on_shelf(last, width, height) { Some(last) } else { None }
+            },
+            ShelfHeuristic::FirstFit => (0..self.shelves.len()).find(|&i| self.fits_on_shelf(i, width, height)),
+            ShelfHeuristic::BestHeightFit => {
+                let mut best_index = None;
+                let mut best_difference = i32::MAX;
+                for i in 0..self.shelves.len() {
+                    let (w,h) = self.rotate_to_shelf(i, width, height);
+                    if self.fits_on_shelf(i, w, h) {
+                        let difference = i32::max(self.shelves[i].height - h, 0);
+                        if difference < best_difference {
+                            best_difference = difference;
+                            best_index = Some(i);
+                        }
+                    }
+                }
+                best_index
+            }
+        };
+        if let Some(index) = shelf_index {
+            return self.add_to_shelf(index, width, height);
+        }
+
+        let (width,height) = if self.allow_rotations && width < height && height <= self.width as i32 {
+            (height,width)
+        } else {
+            (width,height)
+        };
+        let last_shelf = &self.shelves[last];
+        let start_y = last_shelf.start_y + last_shelf.height;
+        if width > self.width as i32 || start_y + height > self.height as i32 {
+            return Rect::default();
+        }
+        self.move_shelf_to_waste_map(last);
+        self.shelves.push(Shelf {current_x:0,start_y,height:0,used_rect:Vec::new()});
+        self.add_to_shelf(last + 1, width, height)
+    }
+
+    fn fits_on_shelf(&self,index:usize,width:i32,height:i32) -> bool {
+        let shelf = &self.shelves[index];
+        let shelf_height = if index == self.shelves.len() - 1 {
+            self.height as i32 - shelf.start_y
+        } else {
+            shelf.height
+        };
+        let width_left = self.width as i32 - shelf.current_x;
+        (width <= width_left && height <= shelf_height) ||
+        (self.allow_rotations && height <= width_left && width <= shelf_height)
+    }
+
+    // Picks the orientation that suits the shelf best: lying flat on tall shelves,
+    // and standing up when lying flat runs out of width.
+    fn rotate_to_shelf(&self,index:usize,width:i32,height:i32) -> (i32,i32) {
+        if !self.allow_rotations {
+            return (width,height);
+        }
+        let shelf = &self.shelves[index];
+        let width_left = self.width as i32 - shelf.current_x;
+        if (width > height && (width > width_left || width < shelf.height)) ||
+           (width < height && height > shelf.height && height <= width_left) {
+            (height,width)
+        } else {
+            (width,height)
+        }
+    }
+
+    fn add_to_shelf(&mut self,index:usize,width:i32,height:i32) -> Rect {
+        let (width,height) = self.rotate_to_shelf(index, width, height);
+        let shelf = &mut self.shelves[index];
+        let new_node = Rect {x:shelf.current_x,y:shelf.start_y,width,height};
+        shelf.current_x += width;
+        shelf.height = i32::max(shelf.height, height);
+        if self.waste_map.is_some() {
+            shelf.used_rect.push(new_node.clone());
+        }
+        self.used_rect.push(new_node.clone());
+        new_node
+    }
+
+    fn move_shelf_to_waste_map(&mut self,index:usize) {
+        let bin_width = self.width as i32;
+        let shelf = &mut self.shelves[index];
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            let free_rect = waste_map.free_rect_mut();
+            for rect in shelf.used_rect.drain(..) {
+                if rect.height < shelf.height {
+                    free_rect.push(Rect {x:rect.x,y:rect.y + rect.height,width:rect.width,height:shelf.height - rect.height});
+                }
+            }
+            if shelf.current_x < bin_width && shelf.height > 0 {
+                free_rect.push(Rect {x:shelf.current_x,y:shelf.start_y,width:bin_width - shelf.current_x,height:shelf.height});
+            }
+            // The rest of the shelf now belongs to the waste map.
+            shelf.current_x = bin_width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShelfBinPack,ShelfHeuristic};
+
+    #[test]
+    fn test_glyph_rows() {
+        // 16px glyphs fill 4 per 64px row; a taller glyph opens a new shelf.
+        let mut shelf = ShelfBinPack::new(64,64,false,false);
+        for i in 0..4 {
+            let rect = shelf.insert(16, 16, ShelfHeuristic::NextFit);
+            assert_eq!((rect.x,rect.y),(i * 16,0));
+        }
+        let rect = shelf.insert(16, 20, ShelfHeuristic::FirstFit);
+        assert_eq!((rect.x,rect.y),(0,16));
+        let rect = shelf.insert(60, 40, ShelfHeuristic::FirstFit);
+        assert_eq!(rect.height,0);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack,Rect};
+use std::str::FromStr;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SkylineHeuristic {
+    BottomLeft,
+    MinWasteFit
+}
+
+impl FromStr for SkylineHeuristic {
+    type Err = String;
+    fn from_str(s:&str) -> Result<Self,Self::Err> {
+        match s {
+            "bottom-left" | "BottomLeft" => Ok(SkylineHeuristic::BottomLeft),
+            "min-waste" | "MinWasteFit" => Ok(SkylineHeuristic::MinWasteFit),
+            _ => Err(format!("unknown skyline heuristic {}",s))
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+struct SkylineNode {
+    x:i32,
+    y:i32,
+    width:i32
+}
+
+/// Skyline bin packer. It only tracks the top edge of the placed rectangles, so
+/// its cost grows with the width of the skyline rather than with the number of sprites.
+/// With a waste map, the gaps left below the skyline are reused for later rectangles.
+#[derive(Debug,Clone)]
+pub struct SkylineBinPack {
+    width:u32,
+    height:u32,
+    allow_rotations:bool,
+    skyline:Vec<SkylineNode>,
+    used_rect:Vec<Rect>,
+    waste_map:Option<MaxRectsBinPack>
+}
+
+impl SkylineBinPack {
+    pub fn new(width:u32,height:u32,rotations:bool,use_waste_map:bool) -> Self {
+        let mut skyline = SkylineBinPack {
+            width:0,
+            height:0,
+            allow_rotations:rotations,
+            skyline:Vec::new(),
+            used_rect:Vec::new(),
+            waste_map:None
+        };
+        skyline.init(width, height, rotations, use_waste_map);
+        skyline
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn used_rect(&self) -> &Vec<Rect> {
+        &self.used_rect
+    }
+
+    /// The waste map follows the same setting.
+    pub fn set_allow_rotations(&mut self,rotations:bool) {
+        self.allow_rotations = rotations;
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            waste_map.set_allow_rotations(rotations);
+        }
+    }
+
+    pub fn init(&mut self,width:u32,height:u32,rotations:bool,use_waste_map:bool) {
+        self.width = width;
+        self.height = height;
+        self.allow_rotations = rotations;
+        self.used_rect.clear();
+        self.skyline.clear();
+        self.skyline.push(SkylineNode {x:0,y:0,width:width as i32});
+        self.waste_map = if use_waste_map {
+            let mut waste_map = MaxRectsBinPack::new(width, height, rotations);
+            waste_map.free_rect_mut().clear();
+            Some(waste_map)
+        } else {
+            None
+        };
+    }
+
+    pub fn insert(&mut self,width:i32,height:i32,method:SkylineHeuristic) -> Rect {
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            let (node,_) = waste_map.insert(width, height, FreeRectChoiceHeuristic::BestShortSideFit);
+            if node.height != 0 {
+                self.used_rect.push(node.clone());
+                return node;
+            }
+        }
+        let (new_node,best_index) = match method {
+            SkylineHeuristic::BottomLeft => self.find_bottom_left(width, height),
+            SkylineHeuristic::MinWasteFit => self.find_min_waste(width, height)
+        };
+        match best_index {
+            Some(index) => {
+                self.add_skyline_level(index, &new_node);
+                self.used_rect.push(new_node.clone());
+                new_node
+            },
+            None => Rect::default()
+        }
+    }
+
+    // Returns the y the rectangle rests at when its left edge is at skyline node `index`.
+    fn rectangle_fits(&self,index:usize,width:i32,height:i32) -> Option<i32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width as i32 {
+            return None;
+        }
+        let mut width_left = width;
+        let mut i = index;
+        let mut y = self.skyline[index].y;
+        while width_left > 0 {
+            y = i32::max(y, self.skyline[i].y);
+            if y + height > self.height as i32 {
+                return None;
+            }
+            width_left -= self.skyline[i].width;
+            i += 1;
+            if i >= self.skyline.len() && width_left > 0 {
+                return None;
+            }
+        }
+        Some(y)
+    }
+
+    fn wasted_area(&self,index:usize,width:i32,y:i32) -> i32 {
+        let mut wasted_area = 0;
+        let rect_left = self.skyline[index].x;
+        let rect_right = rect_left + width;
+        for node in self.skyline[index..].iter() {
+            if node.x >= rect_right || node.x + node.width <= rect_left {
+                break;
+            }
+            let left_side = node.x;
+            let right_side = i32::min(rect_right, left_side + node.width);
+            wasted_area += (right_side - left_side) * (y - node.y);
+        }
+        wasted_area
+    }
+
+    fn find_bottom_left(&self,width:i32,height:i32) -> (Rect,Option<usize>) {
+        let mut best_node = Rect::default();
+        let mut best_height = i32::MAX;
+        let mut best_width = i32::MAX;
+        let mut best_index = None;
+        for (i,node) in self.skyline.iter().enumerate() {
+            if let Some(y) = self.rectangle_fits(i, width, height) {
+                if y + height < best_height || (y + height == best_height && node.width < best_width) {
+                    best_height = y + height;
+                    best_width = node.width;
+                    best_index = Some(i);
+                    best_node = Rect {x:node.x,y,width,height};
+                }
+            }
+            if self.allow_rotations {
+                if let Some(y) = self.rectangle_fits(i, height, width) {
+                    if y + width < best_height || (y + width == best_height && node.width < best_width) {
+                        best_height = y + width;
+                        best_width = node.width;
+                        best_index = Some(i);
+                        best_node = Rect {x:node.x,y,width:height,height:width};
+                    }
+                }
+            }
+        }
+        (best_node,best_index)
+    }
+
+    fn find_min_waste(&self,width:i32,height:i32) -> (Rect,Option<usize>) {
+        let mut best_node = Rect::default();
+        let mut best_height = i32::MAX;
+        let mut best_wasted_area = i32::MAX;
+        let mut best_index = None;
+        for (i,node) in self.skyline.iter().enumerate() {
+            if let Some(y) = self.rectangle_fits(i, width, height) {
+                let wasted_area = self.wasted_area(i, width, y);
+                if wasted_area < best_wasted_area || (wasted_area == best_wasted_area && y + height < best_height) {
+                    best_height = y + height;
+                    best_wasted_area = wasted_area;
+                    best_index = Some(i);
+                    best_node = Rect {x:node.x,y,width,height};
+                }
+            }
+            if self.allow_rotations {
+                if let Some(y) = self.rectangle_fits(i, height, width) {
+                    let wasted_area = self.wasted_area(i, height, y);
+                    if wasted_area < best_wasted_area || (wasted_area == best_wasted_area && y + width < best_height) {
+                        best_height = y + width;
+                        best_wasted_area = wasted_area;
+                        best_index = Some(i);
+                        best_node = Rect {x:node.x,y,width:height,height:width};
+                    }
+                }
+            }
+        }
+        (best_node,best_index)
+    }
+
+    fn add_waste_map_area(&mut self,index:usize,rect:&Rect) {
+        let rect_left = rect.x;
+        let rect_right = rect_left + rect.width;
+        if let Some(waste_map) = self.waste_map.as_mut() {
+            for node in self.skyline[index..].iter() {
+                if node.x >= rect_right || node.x + node.width <= rect_left {
+                    break;
+                }
+                let left_side = node.x;
+                let right_side = i32::min(rect_right, left_side + node.width);
+                if rect.y > node.y {
+                    waste_map.free_rect_mut().push(Rect {x:left_side,y:node.y,width:right_side - left_side,height:rect.y - node.y});
+                }
+            }
+        }
+    }
+
+    fn add_skyline_level(&mut self,index:usize,rect:&Rect) {
+        self.add_waste_map_area(index, rect);
+        self.skyline.insert(index, SkylineNode {x:rect.x,y:rect.y + rect.height,width:rect.width});
+        let i = index + 1;
+        while i < self.skyline.len() {
+            let prev_right = self.skyline[i - 1].x + self.skyline[i - 1].width;
+            if self.skyline[i].x >= prev_right {
+                break;
+            }
+            let shrink = prev_right - self.skyline[i].x;
+            self.skyline[i].x += shrink;
+            self.skyline[i].width -= shrink;
+            if self.skyline[i].width > 0 {
+                break;
+            }
+            self.skyline.remove(i);
+        }
+        self.merge_skylines();
+    }
+
+    fn merge_skylines(&mut self) {
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SkylineBinPack,SkylineHeuristic};
+
+    #[test]
+    fn test_waste_map_reuse() {
+        // The 60x10 rect leaves a 40x10 gap under the 100-wide rect; the waste map fills it.
+        let mut skyline = SkylineBinPack::new(100,100,false,true);
+        skyline.insert(60, 10, SkylineHeuristic::BottomLeft);
+        skyline.insert(100, 20, SkylineHeuristic::BottomLeft);
+        let rect = skyline.insert(40, 10, SkylineHeuristic::BottomLeft);
+        assert_eq!((rect.x,rect.y),(60,0));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::SpriteImage;
+use image::{GenericImageView,RgbaImage};
+
+/// How a legacy sheet is divided into cells.
+#[derive(Debug,Clone,Copy)]
+pub enum GridCells {
+    /// Fixed cell width and height.
+    Size(u32,u32),
+    /// Number of columns and rows; the cell size is derived from the sheet size.
+    Count(u32,u32)
+}
+
+#[derive(Debug,Clone,Copy)]
+pub struct GridSlice {
+    pub cells:GridCells,
+    /// Gap between the sheet edge and the first cell.
+    pub margin:u32,
+    /// Gap between neighbouring cells.
+    pub spacing:u32
+}
+
+impl GridSlice {
+    pub fn new(cells:GridCells) -> Self {
+        GridSlice {cells,margin:0,spacing:0}
+    }
+
+    fn cell_size(&self,width:u32,height:u32) -> (u32,u32) {
+        match self.cells {
+            GridCells::Size(w,h) => (w,h),
+            GridCells::Count(cols,rows) => {
+                let cols = u32::max(cols,1);
+                let rows = u32::max(rows,1);
+                let inner_w = width.saturating_sub(self.margin * 2 + self.spacing * (cols - 1));
+                let inner_h = height.saturating_sub(self.margin * 2 + self.spacing * (rows - 1));
+                (inner_w / cols,inner_h / rows)
+            }
+        }
+    }
+}
+
+/// Cuts `image` into grid cells and returns every cell that has at least one non-transparent pixel.
+/// Cells are named `{name}_{index}` with the row-major cell index, so names stay stable when cells are empty.
+pub fn slice_grid(name:&str,image:&RgbaImage,grid:&GridSlice) -> Vec<SpriteImage> {
+    let (width,height) = image.dimensions();
+    let (cell_w,cell_h) = grid.cell_size(width,height);
+    let mut sprites = Vec::new();
+    if cell_w == 0 || cell_h == 0 {
+        return sprites;
+    }
+    let cols = (width.saturating_sub(grid.margin * 2) + grid.spacing) / (cell_w + grid.spacing);
+    let rows = (height.saturating_sub(grid.margin * 2) + grid.spacing) / (cell_h + grid.spacing);
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = grid.margin + col * (cell_w + grid.spacing);
+            let y = grid.margin + row * (cell_h + grid.spacing);
+            let cell = image.view(x,y,cell_w,cell_h);
+            if cell.pixels().all(|(_,_,pixel)| pixel.0[3] == 0) {
+                continue;
+            }
+            sprites.push(SpriteImage {
+                name:format!("{}_{}",name,row * cols + col),
+                image:cell.to_image(),
+                offset:Some((x as i32,y as i32))
+            });
+        }
+    }
+    sprites
+}
+
+/// Splits a canvas into its connected opaque regions.
+#[derive(Debug,Clone,Copy,Default)]
+pub struct IslandSlice {
+    /// Pixels with an alpha above this value are part of a region.
+    pub alpha_threshold:u8,
+    /// Regions whose bounding boxes are at most this many pixels apart are merged into one sprite.
+    pub merge_distance:u32
+}
+
+/// A slicing front-end applied to a single input image.
+#[derive(Debug,Clone,Copy)]
+pub enum SheetSlice {
+    Grid(GridSlice),
+    Islands(IslandSlice)
+}
+
+impl SheetSlice {
+    pub fn slice(&self,name:&str,image:&RgbaImage) -> Vec<SpriteImage> {
+        match self {
+            SheetSlice::Grid(grid) => slice_grid(name,image,grid),
+            SheetSlice::Islands(islands) => slice_islands(name,image,islands)
+        }
+    }
+}
+
+// Bounding box as (x0,y0,x1,y1) with exclusive ends.
+type Bounds = (u32,u32,u32,u32);
+
+fn bounds_gap(a:&Bounds,b:&Bounds) -> (u32,u32) {
+    let gap_x = u32::max(a.0,b.0).saturating_sub(u32::min(a.2,b.2));
+    let gap_y = u32::max(a.1,b.1).saturating_sub(u32::min(a.3,b.3));
+    (gap_x,gap_y)
+}
+
+fn find_islands(image:&RgbaImage,alpha_threshold:u8) -> Vec<Bounds> {
+    let (width,height) = image.dimensions();
+    let mut visited = vec![false;(width * height) as usize];
+    let mut islands = Vec::new();
+    let mut stack = Vec::new();
+    for start_y in 0..height {
+        for start_x in 0..width {
+            let start = (start_y * width + start_x) as usize;
+            if visited[start] || image.get_pixel(start_x,start_y).0[3] <= alpha_threshold {
+                continue;
+            }
+            visited[start] = true;
+            stack.push((start_x,start_y));
+            let mut bounds = (start_x,start_y,start_x + 1,start_y + 1);
+            while let Some((x,y)) = stack.pop() {
+                bounds = (u32::min(bounds.0,x),u32::min(bounds.1,y),u32::max(bounds.2,x + 1),u32::max(bounds.3,y + 1));
+                for ny in y.saturating_sub(1)..u32::min(y + 2,height) {
+                    for nx in x.saturating_sub(1)..u32::min(x + 2,width) {
+                        let index = (ny * width + nx) as usize;
+                        if !visited[index] && image.get_pixel(nx,ny).0[3] > alpha_threshold {
+                            visited[index] = true;
+                            stack.push((nx,ny));
+                        }
+                    }
+                }
+            }
+            islands.push(bounds);
+        }
+    }
+    islands
+}
+
+/// Finds the connected regions of `image` whose alpha is above the threshold (8-connected),
+/// merges regions closer than `merge_distance` and returns one sprite per region.
+/// Sprites are named `{name}_{index}` in top-to-bottom, left-to-right order.
+pub fn slice_islands(name:&str,image:&RgbaImage,cfg:&IslandSlice) -> Vec<SpriteImage> {
+    let mut islands = find_islands(image,cfg.alpha_threshold);
+    let mut merged = true;
+    while merged {
+        merged = false;
+        let mut i = 0;
+        while i < islands.len() {
+            let mut j = i + 1;
+            while j < islands.len() {
+                let (gap_x,gap_y) = bounds_gap(&islands[i],&islands[j]);
+                if gap_x <= cfg.merge_distance && gap_y <= cfg.merge_distance {
+                    let other = islands.swap_remove(j);
+                    let island = &mut islands[i];
+                    *island = (u32::min(island.0,other.0),u32::min(island.1,other.1),u32::max(island.2,other.2),u32::max(island.3,other.3));
+                    merged = true;
+                } else {
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+    }
+    islands.sort_by_key(|bounds| (bounds.1,bounds.0));
+    islands.iter().enumerate().map(|(index,&(x0,y0,x1,y1))| SpriteImage {
+        name:format!("{}_{}",name,index),
+        image:image.view(x0,y0,x1 - x0,y1 - y0).to_image(),
+        offset:Some((x0 as i32,y0 as i32))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{slice_grid,slice_islands,GridCells,GridSlice,IslandSlice};
+    use image::{Rgba,RgbaImage};
+
+    #[test]
+    fn test_slice_grid() {
+        // 2x2 cells of 4x3 with a margin of 1 and spacing of 2; the bottom-left cell is empty.
+        let mut sheet = RgbaImage::new(1 + 4 + 2 + 4 + 1,1 + 3 + 2 + 3 + 1);
+        for &(x,y) in [(1,1),(7,1),(7,6)].iter() {
+            sheet.put_pixel(x + 1,y + 1,Rgba([255,255,255,255]));
+        }
+        let mut by_size = GridSlice::new(GridCells::Size(4,3));
+        by_size.margin = 1;
+        by_size.spacing = 2;
+        let mut by_count = by_size;
+        by_count.cells = GridCells::Count(2,2);
+        for grid in [by_size,by_count].iter() {
+            let sprites = slice_grid("walk",&sheet,grid);
+            let names:Vec<&str> = sprites.iter().map(|s| s.name.as_str()).collect();
+            assert_eq!(names,vec!["walk_0","walk_1","walk_3"]);
+            assert_eq!(sprites[2].offset,Some((7,6)));
+            assert_eq!(sprites[2].image.dimensions(),(4,3));
+            assert_eq!(sprites[2].image.get_pixel(1,1).0,[255,255,255,255]);
+        }
+    }
+
+    #[test]
+    fn test_slice_islands() {
+        let mut canvas = RgbaImage::new(32,16);
+        let solid = Rgba([255,0,0,255]);
+        // A diagonal stroke (one 8-connected island), a faint pixel and two blobs 3px apart.
+        for i in 0..4 {
+            canvas.put_pixel(2 + i,2 + i,solid);
+        }
+        canvas.put_pixel(10,2,Rgba([255,0,0,10]));
+        for &(x,y) in [(20,10),(21,10),(25,10),(25,11)].iter() {
+            canvas.put_pixel(x,y,solid);
+        }
+        let mut cfg = IslandSlice {alpha_threshold:16,merge_distance:0};
+        let sprites = slice_islands("items",&canvas,&cfg);
+        assert_eq!(sprites.len(),3);
+        assert_eq!(sprites[0].name,"items_0");
+        assert_eq!(sprites[0].offset,Some((2,2)));
+        assert_eq!(sprites[0].image.dimensions(),(4,4));
+        assert_eq!(sprites[1].offset,Some((20,10)));
+        assert_eq!(sprites[2].offset,Some((25,10)));
+
+        cfg.merge_distance = 3;
+        let sprites = slice_islands("items",&canvas,&cfg);
+        assert_eq!(sprites.len(),2);
+        assert_eq!(sprites[1].offset,Some((20,10)));
+        assert_eq!(sprites[1].image.dimensions(),(6,2));
+    }
+}
@@ -0,0 +1,216 @@
+use image::{GenericImageView,RgbaImage};
+use serde_json::{Map,Value};
+use std::convert::TryFrom;
+use std::path::{Component,Path,PathBuf};
+
+struct Frame {
+    name:String,
+    x:u32,
+    y:u32,
+    width:u32,
+    height:u32,
+    rotated:bool,
+    // (x, y) of the trimmed frame inside the original image and the original (width, height).
+    source:Option<((u32,u32),(u32,u32))>
+}
+
+/// Reads `atlas_path` with the descriptor at `desc_path` and writes every sprite to `out_dir` as a PNG.
+/// Returns the number of sprites written.
+pub fn unpack_atlas(atlas_path:&str,desc_path:&str,out_dir:&str) -> Result<usize,String> {
+    let atlas = image::open(atlas_path).map_err(|_| String::from("can't open atlas image"))?.to_rgba();
+    let desc_str = std::fs::read_to_string(desc_path).map_err(|_| String::from("can't read descriptor"))?;
+    let desc:Value = serde_json::from_str(&desc_str).map_err(|_| String::from("descriptor is not valid json"))?;
+    let sprites = unpack_sprites(&atlas,&desc)?;
+    for (name,sprite) in sprites.iter() {
+        let mut out_path = sprite_path(out_dir,name)?;
+        if out_path.extension().and_then(|os_str| os_str.to_str()) != Some("png") {
+            let file_name = format!("{}.png",out_path.file_name().and_then(|os_str| os_str.to_str()).unwrap_or_default());
+            out_path.set_file_name(file_name);
+        }
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|_| String::from("can't create output dir"))?;
+        }
+        sprite.save(&out_path).map_err(|_| format!("can't save {:?}",out_path))?;
+    }
+    Ok(sprites.len())
+}
+
+// Names come from the descriptor, so only plain relative paths are allowed to keep every file inside `out_dir`.
+fn sprite_path(out_dir:&str,name:&str) -> Result<PathBuf,String> {
+    let relative = Path::new(name);
+    if name.is_empty() || !relative.components().all(|component| matches!(component,Component::Normal(_))) {
+        return Err(format!("sprite name {:?} is not a relative path inside the output dir",name));
+    }
+    Ok(Path::new(out_dir).join(relative))
+}
+
+/// Cuts every sprite described by `desc` out of `atlas`, un-rotating rotated frames and
+/// restoring trimmed frames to their original size.
+/// Both the default json written by `sprite_sheet_gen` and TexturePacker's json hash/array formats are accepted.
+pub fn unpack_sprites(atlas:&RgbaImage,desc:&Value) -> Result<Vec<(String,RgbaImage)>,String> {
+    let frames = if let Some(frames) = desc.get("frames") {
+        read_texture_packer_frames(frames)?
+    } else if let Some(sprites) = desc.get("sprites").and_then(|v| v.as_array()) {
+        read_default_frames(sprites)?
+    } else {
+        return Err(String::from("unknown descriptor format"));
+    };
+    let mut out = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let right = frame.x.checked_add(frame.width);
+        let bottom = frame.y.checked_add(frame.height);
+        if right.map(|right| right > atlas.width()).unwrap_or(true) || bottom.map(|bottom| bottom > atlas.height()).unwrap_or(true) {
+            return Err(format!("sprite {} is outside of the atlas",frame.name));
+        }
+        let mut sprite = atlas.view(frame.x,frame.y,frame.width,frame.height).to_image();
+        if frame.rotated {
+            sprite = image::imageops::rotate270(&sprite);
+        }
+        if let Some(((trim_x,trim_y),(source_w,source_h))) = frame.source {
+            let mut source = RgbaImage::new(source_w,source_h);
+            image::imageops::replace(&mut source,&sprite,trim_x,trim_y);
+            sprite = source;
+        }
+        out.push((frame.name,sprite));
+    }
+    Ok(out)
+}
+
+fn get_u32(map:&Map<String,Value>,key:&str) -> Result<u32,String> {
+    let value = map.get(key).and_then(|v| v.as_u64()).ok_or_else(|| format!("missing field {}",key))?;
+    u32::try_from(value).map_err(|_| format!("field {} out of range: {}",key,value))
+}
+
+fn read_default_frames(sprites:&[Value]) -> Result<Vec<Frame>,String> {
+    let mut frames = Vec::with_capacity(sprites.len());
+    for sprite in sprites {
+        let map = sprite.as_object().ok_or_else(|| String::from("sprite is not an object"))?;
+        frames.push(Frame {
+            name:map.get("name").and_then(|v| v.as_str()).ok_or_else(|| String::from("missing field name"))?.to_string(),
+            x:get_u32(map,"x")?,
+            y:get_u32(map,"y")?,
+            width:get_u32(map,"width")?,
+            height:get_u32(map,"height")?,
+            rotated:map.get("rotated").and_then(|v| v.as_bool()).unwrap_or(false),
+            source:None
+        });
+    }
+    Ok(frames)
+}
+
+fn read_texture_packer_frame(name:&str,map:&Map<String,Value>) -> Result<Frame,String> {
+    let rect = map.get("frame").and_then(|v| v.as_object()).ok_or_else(|| String::from("missing field frame"))?;
+    let rotated = map.get("rotated").and_then(|v| v.as_bool()).unwrap_or(false);
+    let (w,h) = (get_u32(rect,"w")?,get_u32(rect,"h")?);
+    let trimmed = map.get("trimmed").and_then(|v| v.as_bool()).unwrap_or(false);
+    let source = if trimmed {
+        let sprite_source = map.get("spriteSourceSize").and_then(|v| v.as_object()).ok_or_else(|| String::from("missing field spriteSourceSize"))?;
+        let source_size = map.get("sourceSize").and_then(|v| v.as_object()).ok_or_else(|| String::from("missing field sourceSize"))?;
+        Some(((get_u32(sprite_source,"x")?,get_u32(sprite_source,"y")?),(get_u32(source_size,"w")?,get_u32(source_size,"h")?)))
+    } else {
+        None
+    };
+    Ok(Frame {
+        name:String::from(name),
+        x:get_u32(rect,"x")?,
+        y:get_u32(rect,"y")?,
+        width:if rotated {h} else {w},
+        height:if rotated {w} else {h},
+        rotated,
+        source
+    })
+}
+
+fn read_texture_packer_frames(frames:&Value) -> Result<Vec<Frame>,String> {
+    let mut out = Vec::new();
+    match frames {
+        Value::Object(map) => {
+            for (name,frame) in map.iter() {
+                let frame = frame.as_object().ok_or_else(|| String::from("frame is not an object"))?;
+                out.push(read_texture_packer_frame(name,frame)?);
+            }
+        },
+        Value::Array(list) => {
+            for frame in list.iter() {
+                let frame = frame.as_object().ok_or_else(|| String::from("frame is not an object"))?;
+                let name = frame.get("filename").and_then(|v| v.as_str()).ok_or_else(|| String::from("missing field filename"))?;
+                out.push(read_texture_packer_frame(name,frame)?);
+            }
+        },
+        _ => return Err(String::from("frames must be an object or an array"))
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sprite_path,unpack_sprites};
+    use image::{Rgba,RgbaImage};
+
+    fn test_atlas() -> RgbaImage {
+        // A 3x2 sprite with a red left column, stored rotated clockwise at (4,0) as 2x3.
+        let mut atlas = RgbaImage::new(8,8);
+        for y in 0..3 {
+            atlas.put_pixel(4,y,Rgba([0,0,255,255]));
+            atlas.put_pixel(5,y,Rgba([0,0,255,255]));
+        }
+        for x in 4..6 {
+            atlas.put_pixel(x,0,Rgba([255,0,0,255]));
+        }
+        atlas
+    }
+
+    #[test]
+    fn test_unpack_default_rotated() {
+        let desc = serde_json::json!({
+            "meta": {"texture":"a.png","width":8,"height":8},
+            "sprites": [{"name":"s","x":4,"y":0,"width":2,"height":3,"rotated":true}]
+        });
+        let sprites = unpack_sprites(&test_atlas(),&desc).unwrap();
+        assert_eq!(sprites[0].0,"s");
+        let sprite = &sprites[0].1;
+        assert_eq!(sprite.dimensions(),(3,2));
+        assert_eq!(sprite.get_pixel(0,0).0,[255,0,0,255]);
+        assert_eq!(sprite.get_pixel(0,1).0,[255,0,0,255]);
+        assert_eq!(sprite.get_pixel(2,1).0,[0,0,255,255]);
+    }
+
+    #[test]
+    fn test_unpack_rejects_bad_frames() {
+        let desc = serde_json::json!({
+            "sprites": [{"name":"s","x":u32::MAX,"y":0,"width":2,"height":3}]
+        });
+        assert!(unpack_sprites(&test_atlas(),&desc).is_err());
+        // 2^32 would wrap to 0 and land inside the atlas if it were truncated.
+        let desc = serde_json::json!({
+            "sprites": [{"name":"s","x":1u64 << 32,"y":0,"width":2,"height":3}]
+        });
+        assert_eq!(unpack_sprites(&test_atlas(),&desc).err().unwrap(),"field x out of range: 4294967296");
+        assert_eq!(sprite_path("out","ui/button.png").unwrap(),std::path::Path::new("out/ui/button.png"));
+        for name in ["../evil.png","ui/../../evil.png","/tmp/evil.png","./s.png",""].iter() {
+            assert!(sprite_path("out",name).is_err(),"{} accepted",name);
+        }
+    }
+
+    #[test]
+    fn test_unpack_texture_packer_trimmed() {
+        let desc = serde_json::json!({
+            "frames": {
+                "s.png": {
+                    "frame": {"x":4,"y":0,"w":3,"h":2},
+                    "rotated": true,
+                    "trimmed": true,
+                    "spriteSourceSize": {"x":1,"y":2,"w":3,"h":2},
+                    "sourceSize": {"w":5,"h":5}
+                }
+            }
+        });
+        let sprites = unpack_sprites(&test_atlas(),&desc).unwrap();
+        assert_eq!(sprites[0].0,"s.png");
+        let sprite = &sprites[0].1;
+        assert_eq!(sprite.dimensions(),(5,5));
+        assert_eq!(sprite.get_pixel(0,0).0,[0,0,0,0]);
+        assert_eq!(sprite.get_pixel(1,2).0,[255,0,0,255]);
+        assert_eq!(sprite.get_pixel(3,3).0,[0,0,255,255]);
+    }
+}
@@ -0,0 +1,146 @@
+use proptest::prelude::*;
+use spritesheet_gen::max_rect::{FreeRectChoiceHeuristic,MaxRectsBinPack,Rect};
+
+const HEURISTICS:[FreeRectChoiceHeuristic;5] = [
+    FreeRectChoiceHeuristic::BestShortSideFit,
+    FreeRectChoiceHeuristic::BestLongSideFit,
+    FreeRectChoiceHeuristic::BestAreaFit,
+    FreeRectChoiceHeuristic::BottomLeftRule,
+    FreeRectChoiceHeuristic::ContactPointRule
+];
+
+fn intersects(a:&Rect,b:&Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn in_bounds(rect:&Rect,width:u32,height:u32) -> bool {
+    rect.x >= 0 && rect.y >= 0 && rect.x + rect.width <= width as i32 && rect.y + rect.height <= height as i32
+}
+
+fn check_invariants(bin:&MaxRectsBinPack) -> Result<(),TestCaseError> {
+    let used = bin.used_rect();
+    for (i,a) in used.iter().enumerate() {
+        prop_assert!(in_bounds(a,bin.width(),bin.height()),"{:?} out of bounds",a);
+        for b in used[i + 1..].iter() {
+            prop_assert!(!intersects(a,b),"{:?} overlaps {:?}",a,b);
+        }
+    }
+    for free in bin.free_rect().iter() {
+        prop_assert!(free.width > 0 && free.height > 0,"degenerate free rect {:?}",free);
+        prop_assert!(in_bounds(free,bin.width(),bin.height()),"free {:?} out of bounds",free);
+        for rect in used.iter() {
+            prop_assert!(!intersects(free,rect),"free {:?} overlaps used {:?}",free,rect);
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn placements_never_overlap(width in 1u32..512,height in 1u32..512,rotations in any::<bool>(),
+                                heuristic in 0usize..5,sizes in prop::collection::vec((1i32..128,1i32..128),0..64)) {
+        let mut bin = MaxRectsBinPack::new(width,height,rotations);
+        for &(w,h) in sizes.iter() {
+            let (rect,rotated) = bin.insert(w,h,HEURISTICS[heuristic]);
+            if rect.height == 0 {
+                continue;
+            }
+            if rotated {
+                prop_assert!(rotations && w != h,"{}x{} rotated without need",w,h);
+                prop_assert_eq!((rect.width,rect.height),(h,w));
+            } else {
+                prop_assert_eq!((rect.width,rect.height),(w,h));
+            }
+        }
+        check_invariants(&bin)?;
+    }
+
+    #[test]
+    fn reserved_rects_stay_free(rotations in any::<bool>(),heuristic in 0usize..5,
+                                reserved in prop::collection::vec((0i32..256,0i32..256,1i32..64,1i32..64),0..8),
+                                sizes in prop::collection::vec((1i32..64,1i32..64),0..48)) {
+        let mut bin = MaxRectsBinPack::new(256,256,rotations);
+        let mut kept = Vec::new();
+        for &(x,y,w,h) in reserved.iter() {
+            let rect = Rect {x,y,width:w,height:h};
+            let free = in_bounds(&rect,256,256) && !kept.iter().any(|other| intersects(other,&rect));
+            prop_assert_eq!(bin.reserve(rect.clone()),free);
+            if free {
+                kept.push(rect);
+            }
+        }
+        for &(w,h) in sizes.iter() {
+            bin.insert(w,h,HEURISTICS[heuristic]);
+        }
+        check_invariants(&bin)?;
+    }
+
+    #[test]
+    fn aligned_placements_share_no_block(width in 1u32..512,height in 1u32..512,rotations in any::<bool>(),
+                                         heuristic in 0usize..5,shift in 0u32..4,
+                                         reserved in prop::collection::vec((0i32..256,0i32..256,1i32..64,1i32..64),0..4),
+                                         sizes in prop::collection::vec((1i32..64,1i32..64),0..48)) {
+        let alignment = 1 << shift;
+        let mut bin = MaxRectsBinPack::new(width,height,rotations);
+        bin.set_alignment(alignment);
+        for &(x,y,w,h) in reserved.iter() {
+            bin.reserve(Rect {x,y,width:w,height:h});
+        }
+        let align_up = |value:i32| (value + alignment as i32 - 1) / alignment as i32 * alignment as i32;
+        let mut blocks:Vec<Rect> = Vec::new();
+        for &(w,h) in sizes.iter() {
+            let (rect,_) = bin.insert(w,h,HEURISTICS[heuristic]);
+            if rect.height == 0 {
+                continue;
+            }
+            prop_assert!(rect.x % alignment as i32 == 0 && rect.y % alignment as i32 == 0,"{:?} not aligned",rect);
+            let block = Rect {x:rect.x,y:rect.y,width:align_up(rect.width),height:align_up(rect.height)};
+            prop_assert!(!blocks.iter().any(|other| intersects(other,&block)),"{:?} shares a block",rect);
+            blocks.push(block);
+        }
+        check_invariants(&bin)?;
+    }
+
+    #[test]
+    fn removal_coalesces_free_space(rotations in any::<bool>(),heuristic in 0usize..5,
+                                    sizes in prop::collection::vec((1i32..64,1i32..64,any::<bool>()),0..64)) {
+        let mut bin = MaxRectsBinPack::new(256,256,rotations);
+        let mut kept = Vec::new();
+        for &(w,h,remove) in sizes.iter() {
+            let (rect,_) = bin.insert(w,h,HEURISTICS[heuristic]);
+            if rect.height == 0 {
+                continue;
+            }
+            if remove {
+                prop_assert!(bin.remove(&rect));
+                prop_assert!(!bin.remove(&rect));
+            } else {
+                kept.push(rect);
+            }
+        }
+        check_invariants(&bin)?;
+        // The free rects are the maximal ones, the same as packing the kept rects from scratch.
+        let mut fresh = MaxRectsBinPack::new(256,256,rotations);
+        for rect in kept.iter() {
+            prop_assert!(fresh.reserve(rect.clone()));
+        }
+        let key = |rect:&Rect| (rect.x,rect.y,rect.width,rect.height);
+        let mut free:Vec<_> = bin.free_rect().iter().map(key).collect();
+        let mut expected:Vec<_> = fresh.free_rect().iter().map(key).collect();
+        free.sort();
+        expected.sort();
+        prop_assert_eq!(free,expected);
+        bin.clear();
+        prop_assert_eq!(bin.free_rect().iter().map(key).collect::<Vec<_>>(),vec![(0,0,256,256)]);
+        prop_assert!(bin.used_rect().is_empty());
+    }
+
+    #[test]
+    fn rotated_fit_is_found(short in 1i32..64,long in 64i32..256,heuristic in 0usize..5) {
+        // The rect only fits the empty bin when rotated.
+        let mut bin = MaxRectsBinPack::new(short as u32,long as u32,true);
+        let (rect,rotated) = bin.insert(long,short,HEURISTICS[heuristic]);
+        prop_assert!(rotated);
+        prop_assert_eq!((rect.width,rect.height),(short,long));
+    }
+}